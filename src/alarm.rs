@@ -0,0 +1,612 @@
+//! Alarms with priorities, delays, acknowledgement, shelving and a history.
+//!
+//! ```ignore
+//! static TANK_HIGH: Alarm = Alarm::new(1, "tank level high", Condition::High { var: &LEVEL, limit: 90.0, hysteresis: 2.0 })
+//!     .priority(1)
+//!     .delay_on(2_000_000)
+//!     .latching();
+//! static PUMP_FAULT: Alarm = Alarm::new(2, "pump fault", Condition::State { var: &PUMP_STATE, value: 3.0 });
+//! static ALARMS: AlarmManager<'static, 2, 32> = AlarmManager::new([&TANK_HIGH, &PUMP_FAULT]);
+//!
+//! loop_async! {{
+//!     ALARMS.cycle();
+//! }}
+//! ```
+//!
+//! The host acknowledges alarms by writing their id to `AlarmManager::ack` and shelves
+//! them by writing the shelve time to `shelve_time` and the id to `shelve`. It reads the
+//! active alarms and the history through `MemVar`.
+//!
+//! Unlike ISA-18.2, only latching alarms wait in `AlarmState::Returned` for an
+//! acknowledgement after their condition cleared. A non-latching alarm that clears while
+//! it is unacknowledged returns to `Normal` right away, the `Cleared` event stays in the
+//! history. Use `latching()` for the return-to-normal unacknowledged state of ISA-18.2.
+
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{MemVar, ScalarVar, Var, VarProps};
+
+/// Id for host commands that apply to all alarms.
+pub const ALL: u16 = 0xffff;
+
+/// Size of an event in the `MemVar` buffer.
+pub const EVENT_SIZE: usize = 16;
+
+/// The condition that raises an alarm.
+pub enum Condition<'a> {
+    /// value above the limit, clears below `limit - hysteresis`
+    High {
+        var: &'a dyn ScalarVar,
+        limit: f64,
+        hysteresis: f64,
+    },
+    /// value below the limit, clears above `limit + hysteresis`
+    Low {
+        var: &'a dyn ScalarVar,
+        limit: f64,
+        hysteresis: f64,
+    },
+    /// value deviates from the setpoint by more than the limit
+    Deviation {
+        var: &'a dyn ScalarVar,
+        setpoint: &'a dyn ScalarVar,
+        limit: f64,
+        hysteresis: f64,
+    },
+    /// value equals a discrete state, e.g. 1.0 for a `Var<bool>` flag
+    State { var: &'a dyn ScalarVar, value: f64 },
+}
+
+impl Condition<'_> {
+    fn evaluate(&self, present: bool) -> bool {
+        // the hysteresis moves the limit once the condition is present
+        let band = |hysteresis: f64| match present {
+            true => hysteresis,
+            false => 0.0,
+        };
+        match self {
+            Condition::High { var, limit, hysteresis } => var.get_f64() > limit - band(*hysteresis),
+            Condition::Low { var, limit, hysteresis } => var.get_f64() < limit + band(*hysteresis),
+            Condition::Deviation {
+                var,
+                setpoint,
+                limit,
+                hysteresis,
+            } => {
+                let deviation = var.get_f64() - setpoint.get_f64();
+                deviation > limit - band(*hysteresis) || -deviation > limit - band(*hysteresis)
+            }
+            Condition::State { var, value } => var.get_f64() == *value,
+        }
+    }
+}
+
+/// State of an alarm as seen by the operator.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum AlarmState {
+    Normal = 0,
+    /// active and not acknowledged
+    Unacked = 1,
+    /// active and acknowledged
+    Acked = 2,
+    /// no longer active but not acknowledged, only for latching alarms, non-latching
+    /// alarms return to `Normal`
+    Returned = 3,
+    Shelved = 4,
+}
+
+/// Kind of an alarm history event.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum EventKind {
+    Raised = 1,
+    Cleared = 2,
+    Acknowledged = 3,
+    Shelved = 4,
+    Unshelved = 5,
+}
+
+/// An entry of the alarm history.
+#[derive(Debug, Copy, Clone)]
+pub struct AlarmEvent {
+    pub seq: u32,
+    /// system time in microseconds
+    pub timestamp: u64,
+    pub id: u16,
+    pub kind: EventKind,
+}
+
+/// An alarm on a condition over variables.
+pub struct Alarm<'a> {
+    id: u16,
+    name: &'static str,
+    condition: Condition<'a>,
+    priority: u8,
+    delay_on_us: u32,
+    delay_off_us: u32,
+    latching: bool,
+    /// the condition is present, before the delays
+    present: SyncCell<bool>,
+    /// time the condition changed and the delay started
+    changed_at: SyncCell<u64>,
+    state: SyncCell<AlarmState>,
+    shelved_until: SyncCell<u64>,
+    /// true while the condition is active (after the delays)
+    pub active: Var<bool>,
+}
+
+impl<'a> Alarm<'a> {
+    /// Creates an alarm, `id` identifies it in host commands and the history.
+    /// 0 (no command) and `ALL` are reserved and rejected.
+    pub const fn new(id: u16, name: &'static str, condition: Condition<'a>) -> Self {
+        assert!(id != 0 && id != ALL, "alarm ids 0 and ALL are reserved");
+        Alarm {
+            id,
+            name,
+            condition,
+            priority: 1,
+            delay_on_us: 0,
+            delay_off_us: 0,
+            latching: false,
+            present: SyncCell::new(false),
+            changed_at: SyncCell::new(0),
+            state: SyncCell::new(AlarmState::Normal),
+            shelved_until: SyncCell::new(0),
+            active: Var::<bool>::new(),
+        }
+    }
+
+    /// sets the priority, 1 is the highest (default)
+    pub const fn priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// the condition must be present for `delay_us` before the alarm is raised
+    pub const fn delay_on(mut self, delay_us: u32) -> Self {
+        self.delay_on_us = delay_us;
+        self
+    }
+
+    /// the condition must be gone for `delay_us` before the alarm clears
+    pub const fn delay_off(mut self, delay_us: u32) -> Self {
+        self.delay_off_us = delay_us;
+        self
+    }
+
+    /// the alarm stays in the active list until it is acknowledged, even if it cleared
+    pub const fn latching(mut self) -> Self {
+        self.latching = true;
+        self
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn state(&self) -> AlarmState {
+        self.state.get()
+    }
+
+    /// returns true if the alarm is shown to the operator (not normal and not shelved)
+    pub fn is_listed(&self) -> bool {
+        !matches!(self.state.get(), AlarmState::Normal | AlarmState::Shelved)
+    }
+
+    /// evaluates the condition, returns the event if the alarm was raised or cleared
+    fn update(&self, now: u64) -> Option<EventKind> {
+        if self.state.get() == AlarmState::Shelved {
+            if now < self.shelved_until.get() {
+                return None;
+            }
+            self.unshelve();
+            return Some(EventKind::Unshelved);
+        }
+        let active = self.active.get();
+        let present = self.condition.evaluate(self.present.get());
+        if present != self.present.get() {
+            self.present.set(present);
+            self.changed_at.set(now);
+        }
+        let delay = match present {
+            true => self.delay_on_us,
+            false => self.delay_off_us,
+        };
+        if present == active || now.saturating_sub(self.changed_at.get()) < delay as u64 {
+            return None;
+        }
+        self.active.set(present);
+        match (present, self.state.get()) {
+            (true, _) => {
+                self.state.set(AlarmState::Unacked);
+                Some(EventKind::Raised)
+            }
+            (false, AlarmState::Unacked) if self.latching => {
+                self.state.set(AlarmState::Returned);
+                Some(EventKind::Cleared)
+            }
+            (false, _) => {
+                self.state.set(AlarmState::Normal);
+                Some(EventKind::Cleared)
+            }
+        }
+    }
+
+    /// returns true if there was something to acknowledge
+    fn acknowledge(&self) -> bool {
+        match self.state.get() {
+            AlarmState::Unacked => self.state.set(AlarmState::Acked),
+            AlarmState::Returned => self.state.set(AlarmState::Normal),
+            _ => return false,
+        }
+        true
+    }
+
+    fn shelve(&self, until: u64) {
+        self.shelved_until.set(until);
+        self.state.set(AlarmState::Shelved);
+        self.active.set(false);
+        self.present.set(false);
+    }
+
+    /// the alarm is evaluated from scratch, it is raised again if the condition is present
+    fn unshelve(&self) {
+        self.state.set(AlarmState::Normal);
+        self.changed_at.set(current_time());
+    }
+}
+
+/// Evaluates alarms, keeps the last `H` events and handles host commands.
+pub struct AlarmManager<'a, const N: usize, const H: usize> {
+    alarms: [&'a Alarm<'a>; N],
+    history: [SyncCell<Option<AlarmEvent>>; H],
+    next_seq: SyncCell<u32>,
+    /// next sequence number read by the host
+    cursor: SyncCell<u32>,
+    /// number of listed alarms
+    pub active_count: Var<u16>,
+    /// number of alarms that need an acknowledgement
+    pub unacked_count: Var<u16>,
+    /// highest priority of the listed alarms, 0 if there are none
+    pub top_priority: Var<u8>,
+    /// host command: id of the alarm to acknowledge or `ALL`, reset to 0 when done
+    pub ack: Var<u16>,
+    /// host command: id of the alarm to shelve for `shelve_time` or `ALL`, a shelve
+    /// time of 0 unshelves it, reset to 0 when done
+    pub shelve: Var<u16>,
+    /// shelve time in seconds
+    pub shelve_time: Var<u32>,
+}
+
+impl<'a, const N: usize, const H: usize> AlarmManager<'a, N, H> {
+    pub const fn new(alarms: [&'a Alarm<'a>; N]) -> Self {
+        assert!(H > 0);
+        AlarmManager {
+            alarms,
+            history: [const { SyncCell::new(None) }; H],
+            next_seq: SyncCell::new(0),
+            cursor: SyncCell::new(0),
+            active_count: Var::<u16>::new(),
+            unacked_count: Var::<u16>::new(),
+            top_priority: Var::<u8>::new(),
+            ack: Var::<u16>::new(),
+            shelve: Var::<u16>::new(),
+            shelve_time: Var::<u32>::new(),
+        }
+    }
+
+    pub fn get(&self, id: u16) -> Option<&'a Alarm<'a>> {
+        self.alarms.iter().copied().find(|alarm| alarm.id == id)
+    }
+
+    /// returns the alarms shown to the operator
+    pub fn listed(&self) -> impl Iterator<Item = &'a Alarm<'a>> + '_ {
+        self.alarms.iter().copied().filter(|alarm| alarm.is_listed())
+    }
+
+    fn selected(&self, id: u16) -> impl Iterator<Item = &'a Alarm<'a>> + '_ {
+        self.alarms
+            .iter()
+            .copied()
+            .filter(move |alarm| id == ALL || alarm.id == id)
+    }
+
+    fn record(&self, id: u16, kind: EventKind) {
+        let seq = self.next_seq.get();
+        self.history[seq as usize % H].set(Some(AlarmEvent {
+            seq,
+            timestamp: current_time(),
+            id,
+            kind,
+        }));
+        self.next_seq.set(seq + 1);
+    }
+
+    /// returns the sequence number of the oldest event that is still stored
+    pub fn oldest_seq(&self) -> u32 {
+        self.next_seq.get().saturating_sub(H as u32)
+    }
+
+    /// returns the event with sequence number `seq`, or the oldest stored event if
+    /// `seq` was already overwritten
+    pub fn event(&self, seq: u32) -> Option<AlarmEvent> {
+        let seq = seq.max(self.oldest_seq());
+        if seq >= self.next_seq.get() {
+            return None;
+        }
+        self.history[seq as usize % H].get()
+    }
+
+    /// acknowledges the alarm with `id`, or all alarms with `ALL`
+    pub fn acknowledge(&self, id: u16) {
+        for alarm in self.selected(id) {
+            if alarm.acknowledge() {
+                self.record(alarm.id, EventKind::Acknowledged);
+            }
+        }
+        self.update_status();
+    }
+
+    /// suppresses the alarm with `id` (or all with `ALL`) for `duration_us`
+    pub fn shelve(&self, id: u16, duration_us: u64) {
+        let until = current_time().saturating_add(duration_us);
+        for alarm in self.selected(id) {
+            alarm.shelve(until);
+            self.record(alarm.id, EventKind::Shelved);
+        }
+        self.update_status();
+    }
+
+    pub fn unshelve(&self, id: u16) {
+        for alarm in self.selected(id) {
+            if alarm.state() == AlarmState::Shelved {
+                alarm.unshelve();
+                self.record(alarm.id, EventKind::Unshelved);
+            }
+        }
+    }
+
+    fn update_status(&self) {
+        self.active_count.set(self.listed().count() as u16);
+        let unacked = self
+            .alarms
+            .iter()
+            .filter(|alarm| matches!(alarm.state(), AlarmState::Unacked | AlarmState::Returned))
+            .count();
+        self.unacked_count.set(unacked as u16);
+        self.top_priority.set(self.listed().map(|alarm| alarm.priority).min().unwrap_or(0));
+    }
+
+    /// Evaluates all alarms and executes host commands, call it once per cycle.
+    pub fn cycle(&self) {
+        let ack = self.ack.get();
+        if ack != 0 {
+            self.acknowledge(ack);
+            self.ack.set(0);
+        }
+        let shelve = self.shelve.get();
+        if shelve != 0 {
+            match self.shelve_time.get() {
+                0 => self.unshelve(shelve),
+                time => self.shelve(shelve, time as u64 * crate::time::SECOND),
+            }
+            self.shelve.set(0);
+        }
+        let now = current_time();
+        for alarm in self.alarms.iter() {
+            if let Some(kind) = alarm.update(now) {
+                self.record(alarm.id, kind);
+            }
+        }
+        self.update_status();
+    }
+}
+
+impl<const N: usize, const H: usize> MemVar for AlarmManager<'_, N, H> {
+    /// subvalue 0 writes the next unread event (seq u32, timestamp u64, id u16,
+    /// kind u8, state u8) and returns 0 if there is none, subvalue 1 writes the listed
+    /// alarms (id u16, state u8, priority u8 each, up to 63)
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        match subvalue {
+            1 => {
+                let mut len = 0;
+                for alarm in self.listed().take(63) {
+                    let entry = alarm.id.to_le_bytes().into_iter().chain([alarm.state() as u8, alarm.priority]);
+                    for byte in entry {
+                        *buffer.add(len) = byte;
+                        len += 1;
+                    }
+                }
+                len as u8
+            }
+            _ => match self.event(self.cursor.get()) {
+                Some(event) => {
+                    self.cursor.set(event.seq + 1);
+                    let state = self.get(event.id).map_or(0, |alarm| alarm.state() as u8);
+                    let bytes = event.seq.to_le_bytes().into_iter()
+                        .chain(event.timestamp.to_le_bytes())
+                        .chain(event.id.to_le_bytes())
+                        .chain([event.kind as u8, state]);
+                    for (i, byte) in bytes.enumerate() {
+                        *buffer.add(i) = byte;
+                    }
+                    EVENT_SIZE as u8
+                }
+                None => 0,
+            },
+        }
+    }
+
+    /// sets the sequence number of the next event to read (u32)
+    unsafe fn from_buffer(&self, buffer: *const u8, _subvalue: u8) -> u8 {
+        self.cursor.set((buffer as *const u32).read_unaligned());
+        4
+    }
+
+    /// true while there are unread events
+    unsafe fn is_dirty(&self) -> bool {
+        self.cursor.get() < self.next_seq.get()
+    }
+
+    unsafe fn clear_dirty(&self) {}
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_subscribed(&self, _value: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::set_system_time;
+
+    fn events<const N: usize, const H: usize>(alarms: &AlarmManager<N, H>) -> Vec<(u64, u16, EventKind)> {
+        (alarms.oldest_seq()..alarms.next_seq.get())
+            .filter_map(|seq| alarms.event(seq))
+            .map(|event| (event.timestamp, event.id, event.kind))
+            .collect()
+    }
+
+    #[test]
+    fn latching_and_acknowledge() {
+        let _time = crate::time::lock_time();
+        let level = Var::<f32>::new();
+        let pump_fault = Var::<bool>::new();
+        let tank_high = Alarm::new(1, "tank level high", Condition::High { var: &level, limit: 90.0, hysteresis: 2.0 })
+            .priority(2)
+            .delay_on(2_000)
+            .latching();
+        let pump = Alarm::new(2, "pump fault", Condition::State { var: &pump_fault, value: 1.0 });
+        let alarms: AlarmManager<2, 8> = AlarmManager::new([&tank_high, &pump]);
+        let cycle = |time: u64| {
+            set_system_time(time);
+            alarms.cycle();
+        };
+
+        cycle(0);
+        level.set(95.0);
+        cycle(1_000);
+        cycle(2_000);
+        assert_eq!(tank_high.state(), AlarmState::Normal);
+        cycle(3_000);
+        assert_eq!(tank_high.state(), AlarmState::Unacked);
+        assert_eq!(alarms.top_priority.get(), 2);
+
+        // within the hysteresis the alarm stays active
+        level.set(89.0);
+        cycle(4_000);
+        assert!(tank_high.active.get());
+        level.set(80.0);
+        cycle(5_000);
+        assert_eq!(tank_high.state(), AlarmState::Returned);
+        assert_eq!(alarms.active_count.get(), 1);
+
+        pump_fault.set(true);
+        cycle(6_000);
+        assert_eq!(alarms.top_priority.get(), 1);
+        assert_eq!(alarms.unacked_count.get(), 2);
+
+        alarms.ack.set(ALL);
+        cycle(7_000);
+        assert_eq!(alarms.ack.get(), 0);
+        assert_eq!(tank_high.state(), AlarmState::Normal);
+        assert_eq!(pump.state(), AlarmState::Acked);
+        assert_eq!((alarms.active_count.get(), alarms.unacked_count.get()), (1, 0));
+
+        pump_fault.set(false);
+        cycle(8_000);
+        assert_eq!(pump.state(), AlarmState::Normal);
+        assert_eq!(
+            events(&alarms),
+            [
+                (3_000, 1, EventKind::Raised),
+                (5_000, 1, EventKind::Cleared),
+                (6_000, 2, EventKind::Raised),
+                (7_000, 1, EventKind::Acknowledged),
+                (7_000, 2, EventKind::Acknowledged),
+                (8_000, 2, EventKind::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn shelve() {
+        let _time = crate::time::lock_time();
+        let pump_fault = Var::<bool>::new();
+        let pump = Alarm::new(2, "pump fault", Condition::State { var: &pump_fault, value: 1.0 });
+        let alarms: AlarmManager<1, 4> = AlarmManager::new([&pump]);
+        let cycle = |time: u64| {
+            set_system_time(time);
+            alarms.cycle();
+        };
+
+        pump_fault.set(true);
+        alarms.shelve_time.set(10);
+        alarms.shelve.set(2);
+        cycle(1_000);
+        assert_eq!(pump.state(), AlarmState::Shelved);
+        assert_eq!(alarms.active_count.get(), 0);
+        cycle(10_000_999);
+        assert_eq!(pump.state(), AlarmState::Shelved);
+        cycle(10_001_000);
+        cycle(10_002_000);
+        assert_eq!(pump.state(), AlarmState::Unacked);
+        assert_eq!(
+            events(&alarms),
+            [
+                (1_000, 2, EventKind::Shelved),
+                (10_001_000, 2, EventKind::Unshelved),
+                (10_002_000, 2, EventKind::Raised),
+            ]
+        );
+        // the history keeps the last 4 events
+        pump_fault.set(false);
+        cycle(10_003_000);
+        pump_fault.set(true);
+        cycle(10_004_000);
+        assert_eq!(alarms.oldest_seq(), 1);
+        assert_eq!(alarms.event(0).unwrap().kind, EventKind::Unshelved);
+    }
+
+    #[test]
+    fn non_latching_clears_unacknowledged() {
+        let _time = crate::time::lock_time();
+        let pump_fault = Var::<bool>::new();
+        let pump = Alarm::new(2, "pump fault", Condition::State { var: &pump_fault, value: 1.0 });
+        let alarms: AlarmManager<1, 4> = AlarmManager::new([&pump]);
+        set_system_time(0);
+        pump_fault.set(true);
+        alarms.cycle();
+        assert_eq!(pump.state(), AlarmState::Unacked);
+        pump_fault.set(false);
+        set_system_time(1_000);
+        alarms.cycle();
+        assert_eq!(pump.state(), AlarmState::Normal);
+        assert_eq!(alarms.unacked_count.get(), 0);
+        assert_eq!(events(&alarms), [(0, 2, EventKind::Raised), (1_000, 2, EventKind::Cleared)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "reserved")]
+    fn reserved_id() {
+        let flag = Var::<bool>::new();
+        let _ = Alarm::new(0, "no id", Condition::State { var: &flag, value: 1.0 });
+    }
+}
@@ -1,7 +1,8 @@
-use core::task::{RawWaker, RawWakerVTable};
-use futures::{future::{Either, select}, Future };
+use core::task::{Poll, RawWaker, RawWakerVTable};
+use futures::{future::{self, Either, select}, Future };
 use futures::future::FusedFuture;
 use core::pin::Pin;
+use crate::poll::poll_called;
 
 pub struct State<'a, T> {
     pub future: Pin<&'a mut dyn FusedFuture<Output = T>>,
@@ -26,6 +27,100 @@ pub async fn wait_or<A, B>(wait_for: impl Future<Output = A>, err: impl Future<O
   }
 }
 
+/// Becomes ready as soon as `condition` returns true.
+/// The condition is evaluated once per poll, e.g. once per cycle.
+///
+/// # Example
+///
+/// ```ignore
+/// until(|| PRESSURE.get() > 500 && !ESTOP.get()).await;
+/// ```
+pub async fn until(condition: impl Fn() -> bool) {
+    future::poll_fn(|_| {
+        poll_called();
+        if condition() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// Pins the element at `index` of a pinned array of futures.
+fn pin_index<F, const N: usize>(futures: Pin<&mut [F; N]>, index: usize) -> Pin<&mut F> {
+    // SAFETY: the array is pinned and its elements are never moved out of it
+    unsafe { futures.map_unchecked_mut(|futures| &mut futures[index]) }
+}
+
+/// Becomes ready as soon as any of the given futures becomes ready.
+/// Returns the index of the future that finished first together with its output.
+/// If several futures finish in the same poll, the lowest index wins.
+///
+/// Futures of different types can be combined by passing `Pin<&mut dyn Future<Output = T>>`.
+/// An empty array is rejected at compile time, it would never become ready.
+///
+/// # Example
+///
+/// ```ignore
+/// // waits until any limit switch trips
+/// let (switch, _) = select_array([LS1.pos(), LS2.pos(), LS3.pos()]).await;
+/// ```
+pub async fn select_array<F: Future, const N: usize>(futures: [F; N]) -> (usize, F::Output) {
+    const { assert!(N > 0, "select_array needs at least one future") };
+    futures::pin_mut!(futures);
+    future::poll_fn(|cx| {
+        for index in 0..N {
+            if let Poll::Ready(output) = pin_index(futures.as_mut(), index).poll(cx) {
+                return Poll::Ready((index, output));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Becomes ready as soon as at least `n` of the given futures became ready.
+/// Returns the outputs of all futures that finished, in the order of the input array.
+/// Futures that already finished are not polled again.
+/// If `n` is larger than `N`, all futures are awaited.
+pub async fn select_n<F: Future, const N: usize>(n: usize, futures: [F; N]) -> [Option<F::Output>; N] {
+    let mut outputs: [Option<F::Output>; N] = core::array::from_fn(|_| None);
+    let mut finished = 0;
+    futures::pin_mut!(futures);
+    future::poll_fn(|cx| {
+        for (index, output) in outputs.iter_mut().enumerate() {
+            if output.is_none() {
+                if let Poll::Ready(value) = pin_index(futures.as_mut(), index).poll(cx) {
+                    *output = Some(value);
+                    finished += 1;
+                }
+            }
+        }
+        if finished >= n.min(N) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+    outputs
+}
+
+/// Becomes ready when all of the given futures became ready.
+/// Returns the outputs in the order of the input array.
+///
+/// # Example
+///
+/// ```ignore
+/// // waits until all doors are closed
+/// join_array([DOOR1.pos(), DOOR2.pos()]).await;
+/// ```
+pub async fn join_array<F: Future, const N: usize>(futures: [F; N]) -> [F::Output; N] {
+    // select_n(N, ..) only returns once every future has finished
+    select_n(N, futures).await.map(Option::unwrap)
+}
+
 /// Creates an RawWaker that does nothing.
 pub fn raw_waker() -> RawWaker {
     fn clone(_: *const ()) -> RawWaker {
@@ -37,3 +132,96 @@ pub fn raw_waker() -> RawWaker {
     let vtable = &RawWakerVTable::new(clone, wake, wake, drop);
     RawWaker::new(0 as *const (), vtable)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::task::Context;
+    use futures::task::noop_waker_ref;
+
+    /// polls `future` once per simulated cycle, `step` runs before every poll
+    fn run<F: Future>(future: F, mut step: impl FnMut(usize)) -> (usize, F::Output) {
+        futures::pin_mut!(future);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for cycle in 0..100 {
+            step(cycle);
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return (cycle, output);
+            }
+        }
+        panic!("future did not finish");
+    }
+
+    #[test]
+    fn until_condition() {
+        let level = Cell::new(0);
+        let (cycle, ()) = run(until(|| level.get() > 2), |cycle| level.set(cycle));
+        assert_eq!(cycle, 3);
+    }
+
+    #[test]
+    fn select_lowest_index_wins() {
+        let switches = [Cell::new(false), Cell::new(false), Cell::new(false)];
+        let future = select_array([0, 1, 2].map(|i| {
+            let switches = &switches;
+            async move {
+                until(|| switches[i].get()).await;
+                i * 10
+            }
+        }));
+        let (cycle, output) = run(future, |cycle| {
+            if cycle == 2 {
+                switches[2].set(true);
+                switches[1].set(true);
+            }
+        });
+        assert_eq!((cycle, output), (2, (1, 10)));
+    }
+
+    #[test]
+    fn select_n_and_join() {
+        let done = [Cell::new(false), Cell::new(false), Cell::new(false)];
+        let futures = || {
+            [0, 1, 2].map(|i| {
+                let done = &done;
+                until(move || done[i].get())
+            })
+        };
+        let reset = || done.iter().for_each(|flag| flag.set(false));
+
+        // futures 0 and 2 finish in cycles 0 and 1
+        let (cycle, outputs) = run(select_n(2, futures()), |cycle| {
+            if cycle < 3 {
+                done[cycle * 2 % 3].set(true);
+            }
+        });
+        assert_eq!((cycle, outputs), (1, [Some(()), None, Some(())]));
+
+        // n larger than N waits for all
+        reset();
+        let (cycle, outputs) = run(select_n(5, futures()), |cycle| {
+            if cycle < 3 {
+                done[cycle].set(true);
+            }
+        });
+        assert_eq!((cycle, outputs), (2, [Some(()); 3]));
+
+        reset();
+        let (cycle, outputs) = run(join_array(futures()), |cycle| {
+            if cycle < 3 {
+                done[2 - cycle].set(true);
+            }
+        });
+        assert_eq!((cycle, outputs), (2, [(); 3]));
+    }
+
+    #[test]
+    fn wait_or_first_wins() {
+        let ok = Cell::new(false);
+        let failed = Cell::new(false);
+        let future = wait_or(until(|| ok.get()), until(|| failed.get()));
+        let (_, result) = run(future, |cycle| failed.set(cycle == 1));
+        assert_eq!(result, Err(()));
+    }
+}
@@ -0,0 +1,62 @@
+//! Decodes `binlog!` output with the format strings of a firmware image.
+//!
+//! Usage: `pilot_logdecode <firmware image> [log file]`, reads stdin without log file.
+
+use pilot_sys::binlog_decode::{Decoder, FormatTable, Output};
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use std::{env, fs};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <firmware image> [log file]", args[0]);
+        return ExitCode::FAILURE;
+    }
+
+    let table = match fs::read(&args[1]) {
+        Ok(image) => FormatTable::from_image(&image),
+        Err(err) => {
+            eprintln!("cannot read {}: {}", args[1], err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if table.is_empty() {
+        eprintln!("no format strings found in {}", args[1]);
+    }
+
+    let mut input: Box<dyn Read> = match args.get(2) {
+        Some(path) => match fs::File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("cannot open {}: {}", path, err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin()),
+    };
+
+    let mut decoder = Decoder::new(&table);
+    let mut stdout = io::stdout();
+    let mut buffer = [0u8; 1024];
+    loop {
+        let len = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) => {
+                eprintln!("read error: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+        for output in decoder.feed(&buffer[..len]) {
+            let result = match output {
+                Output::Text(_) => write!(stdout, "{}", output),
+                _ => writeln!(stdout, "{}", output),
+            };
+            if result.and_then(|_| stdout.flush()).is_err() {
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,220 @@
+//! Compact binary logging with deferred formatting.
+//!
+//! `binlog!` does not format on the device. The format string is stored once in the
+//! firmware image, tagged with `MAGIC`, and only its id and the raw arguments are sent:
+//!
+//! ```ignore
+//! binlog!(Level::Info, "valve {} opened after {} ms", valve, elapsed_ms);
+//! ```
+//!
+//! The host turns the stream back into text with the format strings found in the
+//! firmware image, see `binlog_decode` and the `pilot_logdecode` binary (both need
+//! the `std` feature).
+//!
+//! # Stream format
+//!
+//! A frame is `FRAME_START`, the length of the rest of the frame (u8), the format id
+//! (u32 LE) and the arguments. Every argument is a type tag followed by its value in
+//! little endian, strings are prefixed with their length (u8). Bytes outside of frames
+//! are regular text output, a `FRAME_START` byte in the text is escaped as
+//! `FRAME_START, 0` by `print::PutcharSink` (frames are never empty).
+//!
+//! The format id is the FNV-1a hash of the level and the format string, so the same
+//! format string logged with two levels has two ids.
+
+use crate::log::Level;
+
+/// Marks a format string entry in the firmware image.
+pub const MAGIC: [u8; 8] = *b"PLOGFMT\x01";
+/// Size of an entry before the format string: magic, id (u32), level (u8), length (u16).
+pub const ENTRY_HEADER: usize = 15;
+/// Marks the start of a binary frame in the output stream.
+pub const FRAME_START: u8 = 0x1e;
+/// Maximum frame size, arguments that do not fit are dropped.
+pub const MAX_FRAME: usize = 64;
+
+/// Type tags of the encoded arguments.
+pub mod tag {
+    pub const U8: u8 = 1;
+    pub const U16: u8 = 2;
+    pub const U32: u8 = 3;
+    pub const U64: u8 = 4;
+    pub const I8: u8 = 5;
+    pub const I16: u8 = 6;
+    pub const I32: u8 = 7;
+    pub const I64: u8 = 8;
+    pub const BOOL: u8 = 9;
+    pub const F32: u8 = 10;
+    pub const F64: u8 = 11;
+    pub const STR: u8 = 12;
+}
+
+/// FNV-1a hash.
+pub const fn fnv1a(bytes: &[u8]) -> u32 {
+    fnv1a_extend(0x811c_9dc5, bytes)
+}
+
+const fn fnv1a_extend(mut hash: u32, bytes: &[u8]) -> u32 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Id of a format string logged with `level`, the FNV-1a hash of the level followed by
+/// the format string.
+pub const fn format_id(level: u8, format: &[u8]) -> u32 {
+    fnv1a_extend(fnv1a(&[level]), format)
+}
+
+/// Builds the image entry of a format string, `N` must be `ENTRY_HEADER + format.len()`.
+pub const fn entry<const N: usize>(level: Level, format: &str) -> [u8; N] {
+    let bytes = format.as_bytes();
+    assert!(N == ENTRY_HEADER + bytes.len() && bytes.len() <= u16::MAX as usize);
+
+    let mut entry = [0u8; N];
+    let id = format_id(level as u8, bytes).to_le_bytes();
+    let len = (bytes.len() as u16).to_le_bytes();
+    let mut i = 0;
+    while i < MAGIC.len() {
+        entry[i] = MAGIC[i];
+        i += 1;
+    }
+    entry[8] = id[0];
+    entry[9] = id[1];
+    entry[10] = id[2];
+    entry[11] = id[3];
+    entry[12] = level as u8;
+    entry[13] = len[0];
+    entry[14] = len[1];
+    i = 0;
+    while i < bytes.len() {
+        entry[ENTRY_HEADER + i] = bytes[i];
+        i += 1;
+    }
+    entry
+}
+
+/// An argument that can be sent in a binary frame.
+pub trait Encode {
+    fn encode(&self, frame: &mut Frame);
+}
+
+macro_rules! encode_impl {
+    ($t:ty, $tag:expr) => {
+        impl Encode for $t {
+            fn encode(&self, frame: &mut Frame) {
+                frame.push_arg($tag, &self.to_le_bytes());
+            }
+        }
+    };
+}
+
+encode_impl!(u8, tag::U8);
+encode_impl!(u16, tag::U16);
+encode_impl!(u32, tag::U32);
+encode_impl!(u64, tag::U64);
+encode_impl!(i8, tag::I8);
+encode_impl!(i16, tag::I16);
+encode_impl!(i32, tag::I32);
+encode_impl!(i64, tag::I64);
+encode_impl!(f32, tag::F32);
+encode_impl!(f64, tag::F64);
+
+impl Encode for bool {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push_arg(tag::BOOL, &[*self as u8]);
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, frame: &mut Frame) {
+        // strings are shortened to the space left in the frame
+        let bytes = self.as_bytes();
+        let len = bytes.len().min(MAX_FRAME.saturating_sub(frame.len + 2));
+        if frame.push_bytes(&[tag::STR, len as u8]) {
+            frame.push_bytes(&bytes[..len]);
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, frame: &mut Frame) {
+        (**self).encode(frame)
+    }
+}
+
+/// A binary frame under construction.
+pub struct Frame {
+    buffer: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Frame {
+    /// Starts a frame for the format string `entry` created by `entry`.
+    pub fn new(entry: &'static [u8]) -> Frame {
+        // read the id through a volatile pointer, so the entry is kept in the image
+        let id: [u8; 4] = unsafe { core::ptr::read_volatile(entry[8..12].as_ptr() as *const [u8; 4]) };
+        let mut frame = Frame {
+            buffer: [0; MAX_FRAME],
+            len: 2,
+        };
+        frame.buffer[0] = FRAME_START;
+        frame.push_bytes(&id);
+        frame
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) -> bool {
+        let end = self.len + bytes.len();
+        if end > MAX_FRAME {
+            return false;
+        }
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        true
+    }
+
+    /// appends a tagged argument, it is dropped if the frame is full
+    pub fn push_arg(&mut self, tag: u8, value: &[u8]) {
+        if self.len + 1 + value.len() <= MAX_FRAME {
+            self.push_bytes(&[tag]);
+            self.push_bytes(value);
+        }
+    }
+
+    /// appends an argument
+    pub fn push<T: Encode + ?Sized>(&mut self, value: &T) {
+        value.encode(self);
+    }
+
+    /// returns the encoded frame
+    pub fn as_bytes(&mut self) -> &[u8] {
+        self.buffer[1] = (self.len - 2) as u8;
+        &self.buffer[..self.len]
+    }
+
+    /// writes the frame to the log sink
+    pub fn send(mut self) {
+        crate::log::sink().write_bytes(self.as_bytes());
+    }
+}
+
+/// Logs a message in the binary format if it passes the log filters.
+/// The level must be a constant.
+#[macro_export]
+macro_rules! binlog {
+    ($level:expr, $format:literal $(, $arg:expr)* $(,)?) => ({
+        const LEVEL: $crate::log::Level = $level;
+        const FORMAT: &str = $format;
+        static ENTRY: [u8; $crate::binlog::ENTRY_HEADER + FORMAT.len()] = $crate::binlog::entry(LEVEL, FORMAT);
+        if $crate::log::enabled(LEVEL, module_path!()) {
+            #[allow(unused_mut)]
+            let mut frame = $crate::binlog::Frame::new(&ENTRY);
+            $( frame.push(&$arg); )*
+            frame.send();
+        }
+    });
+}
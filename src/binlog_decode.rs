@@ -0,0 +1,418 @@
+//! Host side decoder for the frames written by `binlog!`.
+//!
+//! ```ignore
+//! let table = FormatTable::from_image(&std::fs::read("firmware.elf")?);
+//! let mut decoder = Decoder::new(&table);
+//! for output in decoder.feed(&received_bytes) {
+//!     println!("{}", output);
+//! }
+//! ```
+
+use crate::binlog::{format_id, tag, ENTRY_HEADER, FRAME_START, MAGIC};
+use crate::log::Level;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A format string found in the firmware image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatEntry {
+    pub level: Option<Level>,
+    pub format: String,
+}
+
+/// The format strings of a firmware image, indexed by id.
+#[derive(Debug, Default)]
+pub struct FormatTable {
+    entries: HashMap<u32, FormatEntry>,
+}
+
+fn level_from_u8(value: u8) -> Option<Level> {
+    match value {
+        1 => Some(Level::Error),
+        2 => Some(Level::Warn),
+        3 => Some(Level::Info),
+        4 => Some(Level::Debug),
+        5 => Some(Level::Trace),
+        _ => None,
+    }
+}
+
+impl FormatTable {
+    /// Collects all format strings from a firmware image (ELF or raw binary).
+    pub fn from_image(image: &[u8]) -> FormatTable {
+        let mut entries = HashMap::new();
+        let mut pos = 0;
+        while pos + ENTRY_HEADER <= image.len() {
+            if image[pos..pos + MAGIC.len()] != MAGIC {
+                pos += 1;
+                continue;
+            }
+            let header = &image[pos..pos + ENTRY_HEADER];
+            let id = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+            let len = u16::from_le_bytes([header[13], header[14]]) as usize;
+            let start = pos + ENTRY_HEADER;
+            match image.get(start..start + len) {
+                // the id check skips false positives of the magic
+                Some(format) if format_id(header[12], format) == id => {
+                    entries.insert(
+                        id,
+                        FormatEntry {
+                            level: level_from_u8(header[12]),
+                            format: String::from_utf8_lossy(format).into_owned(),
+                        },
+                    );
+                    pos = start + len;
+                }
+                _ => pos += 1,
+            }
+        }
+        FormatTable { entries }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&FormatEntry> {
+        self.entries.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A decoded argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Unsigned(u64),
+    Signed(i64),
+    Bool(bool),
+    Float(f64),
+    Str(String),
+}
+
+/// Decodes the arguments of a frame, stops at the first malformed argument.
+pub fn decode_args(mut bytes: &[u8]) -> Vec<Arg> {
+    fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+        let value: [u8; N] = bytes.get(..N)?.try_into().ok()?;
+        *bytes = &bytes[N..];
+        Some(value)
+    }
+
+    let mut args = Vec::new();
+    while let Some([arg_tag]) = take::<1>(&mut bytes) {
+        let arg = match arg_tag {
+            tag::U8 => take::<1>(&mut bytes).map(|b| Arg::Unsigned(u8::from_le_bytes(b) as u64)),
+            tag::U16 => take::<2>(&mut bytes).map(|b| Arg::Unsigned(u16::from_le_bytes(b) as u64)),
+            tag::U32 => take::<4>(&mut bytes).map(|b| Arg::Unsigned(u32::from_le_bytes(b) as u64)),
+            tag::U64 => take::<8>(&mut bytes).map(|b| Arg::Unsigned(u64::from_le_bytes(b))),
+            tag::I8 => take::<1>(&mut bytes).map(|b| Arg::Signed(i8::from_le_bytes(b) as i64)),
+            tag::I16 => take::<2>(&mut bytes).map(|b| Arg::Signed(i16::from_le_bytes(b) as i64)),
+            tag::I32 => take::<4>(&mut bytes).map(|b| Arg::Signed(i32::from_le_bytes(b) as i64)),
+            tag::I64 => take::<8>(&mut bytes).map(|b| Arg::Signed(i64::from_le_bytes(b))),
+            tag::BOOL => take::<1>(&mut bytes).map(|[b]| Arg::Bool(b != 0)),
+            tag::F32 => take::<4>(&mut bytes).map(|b| Arg::Float(f32::from_le_bytes(b) as f64)),
+            tag::F64 => take::<8>(&mut bytes).map(|b| Arg::Float(f64::from_le_bytes(b))),
+            tag::STR => take::<1>(&mut bytes).and_then(|[len]| {
+                let value = bytes.get(..len as usize)?;
+                bytes = &bytes[len as usize..];
+                Some(Arg::Str(String::from_utf8_lossy(value).into_owned()))
+            }),
+            _ => None,
+        };
+        match arg {
+            Some(arg) => args.push(arg),
+            None => break,
+        }
+    }
+    args
+}
+
+fn format_arg(out: &mut String, arg: &Arg, spec: &str) {
+    use std::fmt::Write;
+    let precision = spec.strip_prefix('.').and_then(|p| p.parse::<usize>().ok());
+    let _ = match (arg, spec) {
+        (Arg::Unsigned(v), "x") => write!(out, "{:x}", v),
+        (Arg::Unsigned(v), "X") => write!(out, "{:X}", v),
+        (Arg::Unsigned(v), "b") => write!(out, "{:b}", v),
+        (Arg::Unsigned(v), "o") => write!(out, "{:o}", v),
+        (Arg::Unsigned(v), _) => write!(out, "{}", v),
+        (Arg::Signed(v), "x") => write!(out, "{:x}", v),
+        (Arg::Signed(v), "X") => write!(out, "{:X}", v),
+        (Arg::Signed(v), "b") => write!(out, "{:b}", v),
+        (Arg::Signed(v), "o") => write!(out, "{:o}", v),
+        (Arg::Signed(v), _) => write!(out, "{}", v),
+        (Arg::Bool(v), _) => write!(out, "{}", v),
+        (Arg::Float(v), _) => match precision {
+            Some(precision) => write!(out, "{:.*}", precision, v),
+            None => write!(out, "{}", v),
+        },
+        (Arg::Str(v), "?") => write!(out, "{:?}", v),
+        (Arg::Str(v), _) => write!(out, "{}", v),
+    };
+}
+
+/// Formats a `core::fmt` style format string with decoded arguments.
+///
+/// Supports `{}`, positional `{0}`, `{:?}`, `{:x}`, `{:X}`, `{:b}`, `{:o}`, `{:.N}` and
+/// the `{{` and `}}` escapes. Missing arguments are shown as `{?}`.
+pub fn format(format: &str, args: &[Arg]) -> String {
+    let mut out = String::with_capacity(format.len());
+    let mut next_arg = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let (position, spec) = placeholder.split_once(':').unwrap_or((&placeholder, ""));
+                let index = match position.parse::<usize>() {
+                    Ok(index) => index,
+                    Err(_) => {
+                        next_arg += 1;
+                        next_arg - 1
+                    }
+                };
+                match args.get(index) {
+                    Some(arg) => format_arg(&mut out, arg, spec),
+                    None => out.push_str("{?}"),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Output of the `Decoder`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    /// regular text output between frames
+    Text(String),
+    /// a decoded `binlog!` message
+    Message { level: Option<Level>, text: String },
+    /// a frame whose format id is not in the table
+    Unknown { id: u32 },
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Output::Text(text) => write!(f, "{}", text),
+            Output::Message {
+                level: Some(level),
+                text,
+            } => write!(f, "[{}] {}", level.as_str(), text),
+            Output::Message { level: None, text } => write!(f, "{}", text),
+            Output::Unknown { id } => write!(f, "<unknown format id {:#010x}>", id),
+        }
+    }
+}
+
+/// Splits a byte stream into text and frames and decodes the frames.
+pub struct Decoder<'a> {
+    table: &'a FormatTable,
+    pending: Vec<u8>,
+    /// true at the start of a record, where `print::PutcharSink` writes its marker
+    record_start: bool,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(table: &'a FormatTable) -> Decoder<'a> {
+        Decoder {
+            table,
+            pending: Vec::new(),
+            record_start: true,
+        }
+    }
+
+    fn text(record_start: &mut bool, bytes: &[u8]) -> Output {
+        // drop the start of logstring markers written by `print::PutcharSink`, the same
+        // byte within a line is an apostrophe
+        let mut text = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            if byte == 0x27 && *record_start {
+                *record_start = false;
+                continue;
+            }
+            *record_start = byte == b'\n' || byte == b'\r';
+            text.push(byte);
+        }
+        Output::Text(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    fn frame(&self, frame: &[u8]) -> Output {
+        if frame.len() < 4 {
+            return Output::Text(String::new());
+        }
+        let id = u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]);
+        match self.table.get(id) {
+            Some(entry) => Output::Message {
+                level: entry.level,
+                text: format(&entry.format, &decode_args(&frame[4..])),
+            },
+            None => Output::Unknown { id },
+        }
+    }
+
+    /// Decodes the received bytes, incomplete frames are kept until more bytes arrive.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Output> {
+        self.pending.extend_from_slice(bytes);
+        let mut outputs = Vec::new();
+        let mut pos = 0;
+        while pos < self.pending.len() {
+            let start = match self.pending[pos..].iter().position(|b| *b == FRAME_START) {
+                Some(offset) => pos + offset,
+                None => {
+                    outputs.push(Self::text(&mut self.record_start, &self.pending[pos..]));
+                    pos = self.pending.len();
+                    break;
+                }
+            };
+            if start > pos {
+                outputs.push(Self::text(&mut self.record_start, &self.pending[pos..start]));
+            }
+            let len = match self.pending.get(start + 1) {
+                Some(len) => *len as usize,
+                None => {
+                    pos = start;
+                    break;
+                }
+            };
+            if len == 0 {
+                // an escaped `FRAME_START` in the text
+                outputs.push(Self::text(&mut self.record_start, &[FRAME_START]));
+                pos = start + 2;
+                continue;
+            }
+            let end = start + 2 + len;
+            if end > self.pending.len() {
+                pos = start;
+                break;
+            }
+            outputs.push(self.frame(&self.pending[start + 2..end]));
+            self.record_start = true;
+            pos = end;
+        }
+        self.pending.drain(..pos);
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binlog::{entry, Frame};
+
+    const FORMAT: &str = "valve {} opened after {:.1} ms, {}";
+    static ENTRY: [u8; ENTRY_HEADER + FORMAT.len()] = entry(Level::Info, FORMAT);
+
+    fn table() -> FormatTable {
+        let mut image = b"\x7fELF PLOGFMT garbage".to_vec();
+        image.extend_from_slice(&ENTRY);
+        image.extend_from_slice(b"trailing");
+        FormatTable::from_image(&image)
+    }
+
+    #[test]
+    fn format_table_from_image() {
+        let table = table();
+        assert_eq!(table.len(), 1);
+        let entry = table.get(format_id(Level::Info as u8, FORMAT.as_bytes())).unwrap();
+        assert_eq!(entry.level, Some(Level::Info));
+        assert_eq!(entry.format, FORMAT);
+    }
+
+    #[test]
+    fn same_format_at_two_levels() {
+        static WARN: [u8; ENTRY_HEADER + FORMAT.len()] = entry(Level::Warn, FORMAT);
+        let mut image = ENTRY.to_vec();
+        image.extend_from_slice(&WARN);
+        let table = FormatTable::from_image(&image);
+        assert_eq!(table.len(), 2);
+        let mut decoder = Decoder::new(&table);
+        let mut frames = Frame::new(&ENTRY).as_bytes().to_vec();
+        frames.extend_from_slice(Frame::new(&WARN).as_bytes());
+        let levels: Vec<_> = decoder
+            .feed(&frames)
+            .into_iter()
+            .map(|output| match output {
+                Output::Message { level, .. } => level,
+                output => panic!("unexpected {:?}", output),
+            })
+            .collect();
+        assert_eq!(levels, [Some(Level::Info), Some(Level::Warn)]);
+    }
+
+    #[test]
+    fn escaped_frame_start_in_text() {
+        let table = table();
+        let mut stream = vec![b'a', FRAME_START, 0, b'b'];
+        stream.extend_from_slice(Frame::new(&ENTRY).as_bytes());
+        let mut decoder = Decoder::new(&table);
+        // the escape is split over two reads
+        let mut outputs = decoder.feed(&stream[..2]);
+        outputs.extend(decoder.feed(&stream[2..]));
+        assert_eq!(
+            outputs[..3],
+            [
+                Output::Text("a".into()),
+                Output::Text("\x1e".into()),
+                Output::Text("b".into()),
+            ]
+        );
+        assert!(matches!(outputs[3], Output::Message { .. }));
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let table = table();
+        let mut frame = Frame::new(&ENTRY);
+        frame.push(&3u8);
+        frame.push(&12.25f32);
+        frame.push("ok");
+        let mut stream = b"'can't open\n\r".to_vec();
+        stream.extend_from_slice(frame.as_bytes());
+        stream.extend_from_slice(b"'done\n\r");
+
+        // the frame is split over two reads
+        let mut decoder = Decoder::new(&table);
+        let mut outputs = decoder.feed(&stream[..16]);
+        outputs.extend(decoder.feed(&stream[16..]));
+        assert_eq!(
+            outputs,
+            [
+                Output::Text("can't open\n\r".into()),
+                Output::Message {
+                    level: Some(Level::Info),
+                    text: "valve 3 opened after 12.2 ms, ok".into(),
+                },
+                Output::Text("done\n\r".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_and_truncated_frames() {
+        let table = FormatTable::default();
+        let mut decoder = Decoder::new(&table);
+        let outputs = decoder.feed(&[FRAME_START, 4, 1, 0, 0, 0, FRAME_START, 10, 1]);
+        assert_eq!(outputs, [Output::Unknown { id: 1 }]);
+        // the incomplete frame is kept
+        assert!(decoder.feed(&[]).is_empty());
+    }
+
+    #[test]
+    fn format_specs() {
+        let args = [Arg::Unsigned(255), Arg::Signed(-2), Arg::Str("a".into())];
+        assert_eq!(format("{:x} {1} {{{}}} {:?} {}", &args), "ff -2 {-2} \"a\" {?}");
+    }
+}
@@ -0,0 +1,165 @@
+//! Cooperative cancellation of async tasks.
+//!
+//! ```ignore
+//! static MACHINE: CancelToken = CancelToken::new();
+//! static CONVEYOR: CancelToken = CancelToken::child_of(&MACHINE);
+//!
+//! // cancelled when CONVEYOR or MACHINE is cancelled, MOTOR is switched off in both cases
+//! let result = CONVEYOR
+//!     .run_with_cleanup(async { loop_async! {{ /* ... */ }} }, || MOTOR.set(false))
+//!     .await;
+//!
+//! // e.g. in the emergency stop task
+//! ESTOP.pos().await;
+//! MACHINE.cancel();
+//! ```
+
+use crate::poll::poll_called;
+use crate::sync::SyncCell;
+use core::future::Future;
+use core::task::Poll;
+use futures::future;
+
+/// Error returned when a task was cancelled before it finished.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Cancelled;
+
+/// A cancellation flag that tasks can check or await.
+///
+/// A token created with `child_of` is also cancelled when its parent is cancelled.
+pub struct CancelToken<'a> {
+    cancelled: SyncCell<bool>,
+    parent: Option<&'a CancelToken<'a>>,
+}
+
+impl<'a> CancelToken<'a> {
+    pub const fn new() -> Self {
+        CancelToken {
+            cancelled: SyncCell::new(false),
+            parent: None,
+        }
+    }
+
+    /// creates a token that is cancelled together with `parent`
+    pub const fn child_of(parent: &'a CancelToken<'a>) -> Self {
+        CancelToken {
+            cancelled: SyncCell::new(false),
+            parent: Some(parent),
+        }
+    }
+
+    /// creates a token for a child task that is cancelled together with this token
+    pub fn child(&'a self) -> CancelToken<'a> {
+        Self::child_of(self)
+    }
+
+    /// cancels this token and all of its children
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+    }
+
+    /// clears the cancellation of this token so tasks can be started again,
+    /// a cancelled parent stays cancelled
+    pub fn reset(&self) {
+        self.cancelled.set(false);
+    }
+
+    /// returns true if this token or one of its parents was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get() || self.parent.is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// returns `Err(Cancelled)` if the token was cancelled, useful with `?`
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /// waits until the token is cancelled
+    pub async fn cancelled(&self) {
+        future::poll_fn(|_| {
+            poll_called();
+            if self.is_cancelled() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Runs `task` until it finishes or the token is cancelled.
+    ///
+    /// The cancellation is checked before `task` is polled, a cancelled task is
+    /// dropped without being polled again.
+    pub async fn run<T>(&self, task: impl Future<Output = T>) -> Result<T, Cancelled> {
+        futures::pin_mut!(task);
+        future::poll_fn(|cx| {
+            if self.is_cancelled() {
+                return Poll::Ready(Err(Cancelled));
+            }
+            task.as_mut().poll(cx).map(Ok)
+        })
+        .await
+    }
+
+    /// Like `run`, but calls `cleanup` if the task was cancelled,
+    /// e.g. to drive outputs to a safe state.
+    pub async fn run_with_cleanup<T>(
+        &self,
+        task: impl Future<Output = T>,
+        cleanup: impl FnOnce(),
+    ) -> Result<T, Cancelled> {
+        let result = self.run(task).await;
+        if result.is_err() {
+            cleanup();
+        }
+        result
+    }
+}
+
+impl Default for CancelToken<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs a closure when it is dropped, unless it was disarmed.
+///
+/// Futures are dropped when their task is cancelled, so a guard inside a task
+/// can undo the effects of a partially executed step:
+///
+/// ```ignore
+/// async fn press() {
+///     let _retract = on_drop(|| CYLINDER.set(false));
+///     CYLINDER.set(true);
+///     END_POSITION.pos().await;
+/// }
+/// ```
+pub struct CleanupGuard<F: FnOnce()> {
+    cleanup: Option<F>,
+}
+
+/// creates a `CleanupGuard` that runs `cleanup` when dropped
+pub fn on_drop<F: FnOnce()>(cleanup: F) -> CleanupGuard<F> {
+    CleanupGuard {
+        cleanup: Some(cleanup),
+    }
+}
+
+impl<F: FnOnce()> CleanupGuard<F> {
+    /// prevents the cleanup from running
+    pub fn disarm(mut self) {
+        self.cleanup = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for CleanupGuard<F> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            cleanup();
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Variables computed from other variables.
+//!
+//! ```ignore
+//! static AVG_TEMP: Computed<'static, f32, 3> = Computed::new(
+//!     [&TEMP1, &TEMP2, &TEMP3],
+//!     || (TEMP1.get() + TEMP2.get() + TEMP3.get()) / 3.0,
+//!     Var::<f32>::new(),
+//! );
+//! static ANY_FAULT: Computed<'static, bool, 2> = Computed::new(
+//!     [&PUMP_FAULT, &VALVE_FAULT],
+//!     || PUMP_FAULT.get() || VALVE_FAULT.get(),
+//!     Var::<bool>::new(),
+//! );
+//!
+//! if ANY_FAULT.get() { ... }
+//! ANY_FAULT.pos().await;
+//! ```
+//!
+//! **`compute` must only read the variables listed in `inputs`.** Changes are detected
+//! on the inputs alone, a value that depends on anything else, e.g. the time or a
+//! variable missing from the list, goes stale until a listed input changes.
+//!
+//! The value is computed lazily when it is read, by the program or the host, and at
+//! most once per cycle (system time). It is only recomputed if an input changed. The
+//! host can subscribe to it like to a `Var`, but not write or force it. A waveform
+//! recording (`vcd`) shows the new value when it was computed, so a value that is not
+//! read in a cycle shows up later.
+
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{MemVar, SubscribeMode, Var, VarBuffer, VarChange, VarProps};
+
+/// A read-only variable that is a function of `N` input variables.
+pub struct Computed<'a, T: Default, const N: usize> {
+    inputs: [&'a dyn MemVar; N],
+    compute: fn() -> T,
+    var: Var<T>,
+    /// raw values of the inputs at the last computation
+    snapshot: [SyncCell<u64>; N],
+    /// system time of the last check, `None` before the first computation
+    checked_at: SyncCell<Option<u64>>,
+}
+
+impl<'a, T: Default, const N: usize> Computed<'a, T, N> {
+    /// `var` holds the computed value, create it with `Var::new()`.
+    ///
+    /// `compute` must only read the variables in `inputs`, it is only called again
+    /// after one of them changed.
+    pub const fn new(inputs: [&'a dyn MemVar; N], compute: fn() -> T, var: Var<T>) -> Self {
+        Computed {
+            inputs,
+            compute,
+            var,
+            snapshot: [const { SyncCell::new(0) }; N],
+            checked_at: SyncCell::new(None),
+        }
+    }
+}
+
+impl<T: Default + Copy, const N: usize> Computed<'_, T, N>
+where
+    Var<T>: VarProps<T>,
+{
+    /// returns the raw value of an input (up to 8 bytes, forcing applied)
+    fn read_input(input: &dyn MemVar) -> u64 {
+        let mut buffer = VarBuffer::new();
+        let len = unsafe { input.to_buffer(buffer.as_mut_ptr(), 0) } as usize;
+        let mut raw = [0u8; 8];
+        let len = len.min(8);
+        raw[..len].copy_from_slice(&buffer.0[..len]);
+        u64::from_le_bytes(raw)
+    }
+
+    /// recomputes the value if an input changed since the last computation,
+    /// at most once per cycle
+    pub fn update(&self) {
+        let now = current_time();
+        let first = match self.checked_at.get() {
+            Some(checked_at) if checked_at == now => return,
+            Some(_) => false,
+            None => true,
+        };
+        self.checked_at.set(Some(now));
+        let mut changed = first;
+        for (input, snapshot) in self.inputs.iter().zip(self.snapshot.iter()) {
+            let raw = Self::read_input(*input);
+            if raw != snapshot.get() {
+                snapshot.set(raw);
+                changed = true;
+            }
+        }
+        if changed {
+            self.var.set((self.compute)());
+            // the waveform recorder knows the `Computed`, not the inner variable
+            #[cfg(feature = "std")]
+            crate::vcd::var_changed(self as *const Self as *const ());
+        }
+    }
+
+    /// returns the computed value
+    pub fn get(&self) -> T {
+        self.update();
+        self.var.get()
+    }
+
+    /// subscribe or unsubscribe to changes of the computed value
+    pub fn subscribe(&self, value: SubscribeMode) {
+        self.var.subscribe(value);
+    }
+}
+
+impl<T: Default + Copy, const N: usize> VarChange for Computed<'_, T, N>
+where
+    Var<T>: VarProps<T> + VarChange<VarType = T>,
+{
+    type VarType = T;
+
+    fn get_value(&self) -> T {
+        self.update();
+        self.var.get_value()
+    }
+
+    fn is_posedge(&self, value: T) -> bool {
+        self.update();
+        self.var.is_posedge(value)
+    }
+
+    fn is_negedge(&self, value: T) -> bool {
+        self.update();
+        self.var.is_negedge(value)
+    }
+
+    fn is_unread(&self) -> bool {
+        self.var.is_unread()
+    }
+}
+
+impl<T: Default + Copy + Sync, const N: usize> MemVar for Computed<'_, T, N>
+where
+    Var<T>: VarProps<T> + MemVar,
+{
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        self.update();
+        self.var.to_buffer(buffer, subvalue)
+    }
+
+    /// the value is read-only, writes are ignored
+    unsafe fn from_buffer(&self, _buffer: *const u8, _subvalue: u8) -> u8 {
+        core::mem::size_of::<T>() as u8
+    }
+
+    unsafe fn is_dirty(&self) -> bool {
+        self.update();
+        self.var.is_dirty()
+    }
+
+    unsafe fn clear_dirty(&self) {
+        self.var.clear_dirty()
+    }
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    /// computed values cannot be forced, force the inputs instead
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        self.var.get_subscribed()
+    }
+
+    unsafe fn set_subscribed(&self, value: u8) {
+        self.var.set_subscribed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{lock_time, set_system_time};
+
+    static A: Var<u16> = Var::<u16>::new();
+    static B: Var<bool> = Var::<bool>::new();
+    static CALLS: SyncCell<u32> = SyncCell::new(0);
+
+    fn compute() -> u16 {
+        CALLS.set(CALLS.get() + 1);
+        match B.get() {
+            true => A.get() * 2,
+            false => A.get(),
+        }
+    }
+
+    #[test]
+    fn computed_value() {
+        let _time = lock_time();
+        set_system_time(0);
+        let value: Computed<u16, 2> = Computed::new([&A, &B], compute, Var::<u16>::new());
+        value.subscribe(SubscribeMode::Current);
+        A.set(3);
+        assert_eq!(value.get(), 3);
+        assert_eq!(CALLS.get(), 1);
+
+        // at most once per cycle, even if an input changed
+        A.set(4);
+        assert_eq!(value.get(), 3);
+        assert_eq!(CALLS.get(), 1);
+
+        // in the next cycle only if an input changed
+        set_system_time(1_000);
+        assert_eq!(value.get(), 4);
+        set_system_time(2_000);
+        assert_eq!(value.get(), 4);
+        assert_eq!(CALLS.get(), 2);
+
+        unsafe {
+            value.clear_dirty();
+            B.set(true);
+            set_system_time(3_000);
+            assert!(value.is_dirty());
+            assert_eq!(value.get(), 8);
+            assert_eq!(CALLS.get(), 3);
+        }
+    }
+
+    #[test]
+    fn host_cannot_write() {
+        let _time = lock_time();
+        set_system_time(0);
+        static C: Var<u16> = Var::<u16>::new();
+        fn double() -> u16 {
+            C.get() * 2
+        }
+        let value: Computed<u16, 1> = Computed::new([&C], double, Var::<u16>::new());
+        C.set(5);
+        let mut buffer = VarBuffer::new();
+        unsafe {
+            assert_eq!(value.from_buffer([1u8, 0].as_ptr(), 0), 2);
+            value.set_forced(1);
+            assert_eq!(value.get_forced(), 0);
+            value.to_buffer(buffer.as_mut_ptr(), 0);
+        }
+        assert_eq!(&buffer.0[..2], &10u16.to_le_bytes());
+        assert_eq!(value.get(), 10);
+    }
+}
@@ -0,0 +1,265 @@
+//! Cycle time measurement, jitter statistics and overrun detection.
+//!
+//! The runtime calls `start` at the beginning and `finish` at the end of every cycle
+//! with a free running timestamp in microseconds:
+//!
+//! ```ignore
+//! let now = timer_us();
+//! set_system_time(now);
+//! CYCLE.start(now);
+//! // poll tasks
+//! CYCLE.finish(timer_us());
+//! ```
+
+use crate::sync::SyncCell;
+use crate::var::{Var, VarProps};
+
+/// Number of buckets of the jitter histogram.
+pub const JITTER_BUCKETS: usize = 8;
+
+/// The global cycle monitor.
+pub static CYCLE: CycleMonitor = CycleMonitor::new();
+
+/// Reaction to a cycle overrun, stored in `CycleMonitor::reaction`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum OverrunReaction {
+    /// only count the overrun
+    Ignore = 0,
+    /// print a log message
+    Log = 1,
+    /// call the safe state hook
+    SafeState = 2,
+    /// call the safe state hook and set `halted`, the runtime should stop polling tasks
+    Halt = 3,
+}
+
+impl OverrunReaction {
+    /// converts the value of `CycleMonitor::reaction`, unknown values are ignored
+    pub fn from_u8(value: u8) -> OverrunReaction {
+        match value {
+            1 => OverrunReaction::Log,
+            2 => OverrunReaction::SafeState,
+            3 => OverrunReaction::Halt,
+            _ => OverrunReaction::Ignore,
+        }
+    }
+}
+
+/// Collects statistics about the cycle execution, all times are in microseconds.
+pub struct CycleMonitor {
+    /// configured cycle period, 0 disables jitter and overrun detection
+    pub period: Var<u32>,
+    /// `OverrunReaction`
+    pub reaction: Var<u8>,
+    /// width of a jitter histogram bucket
+    pub jitter_resolution: Var<u32>,
+    /// number of cycles, saturates at `u32::MAX`
+    pub count: Var<u32>,
+    /// execution time of the last cycle
+    pub last: Var<u32>,
+    /// minimum execution time
+    pub min: Var<u32>,
+    /// maximum execution time
+    pub max: Var<u32>,
+    /// average execution time of all cycles since the last reset
+    pub avg: Var<u32>,
+    /// maximum deviation of the cycle start from the period
+    pub max_jitter: Var<u32>,
+    /// bucket `i` counts the cycles with a jitter in `[i, i + 1) * jitter_resolution`,
+    /// the last bucket also counts all larger deviations
+    pub jitter: [Var<u32>; JITTER_BUCKETS],
+    /// number of cycles whose execution time exceeded the period
+    pub overruns: Var<u32>,
+    /// set by `OverrunReaction::Halt`
+    pub halted: Var<bool>,
+    started_at: SyncCell<u64>,
+    // the average uses its own counters, `count` saturates
+    samples: SyncCell<u64>,
+    total: SyncCell<u64>,
+    safe_state: SyncCell<Option<fn()>>,
+}
+
+impl CycleMonitor {
+    pub const fn new() -> CycleMonitor {
+        CycleMonitor {
+            period: Var::<u32>::new(),
+            reaction: Var::<u8>::new(),
+            jitter_resolution: Var::<u32>::new(),
+            count: Var::<u32>::new(),
+            last: Var::<u32>::new(),
+            min: Var::<u32>::new(),
+            max: Var::<u32>::new(),
+            avg: Var::<u32>::new(),
+            max_jitter: Var::<u32>::new(),
+            jitter: [const { Var::<u32>::new() }; JITTER_BUCKETS],
+            overruns: Var::<u32>::new(),
+            halted: Var::<bool>::new(),
+            started_at: SyncCell::new(0),
+            samples: SyncCell::new(0),
+            total: SyncCell::new(0),
+            safe_state: SyncCell::new(None),
+        }
+    }
+
+    /// Configures the cycle period and the overrun reaction.
+    pub fn configure(&self, period_us: u32, reaction: OverrunReaction) {
+        self.period.set(period_us);
+        self.reaction.set(reaction as u8);
+        if self.jitter_resolution.get() == 0 {
+            self.jitter_resolution.set((period_us / 100).max(1));
+        }
+    }
+
+    /// sets the hook that drives the outputs to a safe state on overruns
+    pub fn set_safe_state(&self, hook: fn()) {
+        self.safe_state.set(Some(hook));
+    }
+
+    /// returns true if an overrun halted the program
+    pub fn is_halted(&self) -> bool {
+        self.halted.get()
+    }
+
+    /// resets all statistics
+    pub fn reset(&self) {
+        for var in [&self.count, &self.last, &self.min, &self.max, &self.avg, &self.max_jitter, &self.overruns] {
+            var.set(0);
+        }
+        for bucket in self.jitter.iter() {
+            bucket.set(0);
+        }
+        self.samples.set(0);
+        self.total.set(0);
+        self.halted.set(false);
+    }
+
+    /// Marks the start of a cycle, updates the jitter statistics.
+    pub fn start(&self, now: u64) {
+        let previous = self.started_at.replace(now);
+        let period = self.period.get() as u64;
+        if period == 0 || self.count.get() == 0 {
+            return;
+        }
+
+        let interval = now.saturating_sub(previous);
+        let jitter = interval.abs_diff(period).min(u32::MAX as u64) as u32;
+        if jitter > self.max_jitter.get() {
+            self.max_jitter.set(jitter);
+        }
+        let bucket = (jitter / self.jitter_resolution.get().max(1)) as usize;
+        let bucket = &self.jitter[bucket.min(JITTER_BUCKETS - 1)];
+        bucket.set(bucket.get().saturating_add(1));
+    }
+
+    /// Marks the end of a cycle, updates the execution time statistics and
+    /// reacts to overruns.
+    pub fn finish(&self, now: u64) {
+        let elapsed = now.saturating_sub(self.started_at.get()).min(u32::MAX as u64) as u32;
+        let samples = self.samples.get() + 1;
+        let total = self.total.get() + elapsed as u64;
+
+        self.count.set(self.count.get().saturating_add(1));
+        self.samples.set(samples);
+        self.total.set(total);
+        self.last.set(elapsed);
+        self.avg.set((total / samples) as u32);
+        if samples == 1 || elapsed < self.min.get() {
+            self.min.set(elapsed);
+        }
+        if elapsed > self.max.get() {
+            self.max.set(elapsed);
+        }
+
+        let period = self.period.get();
+        if period > 0 && elapsed > period {
+            self.overrun(elapsed, period);
+        }
+    }
+
+    fn overrun(&self, elapsed: u32, period: u32) {
+        self.overruns.set(self.overruns.get().saturating_add(1));
+        match OverrunReaction::from_u8(self.reaction.get()) {
+            OverrunReaction::Ignore => (),
+            OverrunReaction::Log => crate::println!("cycle overrun: {} us > {} us", elapsed, period),
+            OverrunReaction::SafeState => self.enter_safe_state(),
+            OverrunReaction::Halt => {
+                self.enter_safe_state();
+                self.halted.set(true);
+            }
+        }
+    }
+
+    fn enter_safe_state(&self) {
+        if let Some(hook) = self.safe_state.get() {
+            hook();
+        }
+    }
+}
+
+impl Default for CycleMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(monitor: &CycleMonitor, start: u64, elapsed: u64) {
+        monitor.start(start);
+        monitor.finish(start + elapsed);
+    }
+
+    #[test]
+    fn execution_time_statistics() {
+        let monitor = CycleMonitor::new();
+        for (i, elapsed) in [30, 10, 20].into_iter().enumerate() {
+            run(&monitor, i as u64 * 100, elapsed);
+        }
+        assert_eq!(monitor.count.get(), 3);
+        assert_eq!((monitor.min.get(), monitor.max.get(), monitor.avg.get()), (10, 30, 20));
+        assert_eq!(monitor.last.get(), 20);
+
+        monitor.reset();
+        run(&monitor, 1_000, 40);
+        assert_eq!((monitor.count.get(), monitor.min.get(), monitor.avg.get()), (1, 40, 40));
+    }
+
+    #[test]
+    fn average_survives_saturated_count() {
+        let monitor = CycleMonitor::new();
+        run(&monitor, 0, 10);
+        monitor.count.set(u32::MAX);
+        run(&monitor, 100, 30);
+        assert_eq!(monitor.count.get(), u32::MAX);
+        assert_eq!(monitor.avg.get(), 20);
+    }
+
+    #[test]
+    fn jitter_histogram() {
+        let monitor = CycleMonitor::new();
+        monitor.configure(1_000, OverrunReaction::Ignore);
+        // the first cycle has no previous start and is not counted
+        for start in [0, 1_000, 2_015, 3_000, 5_000] {
+            run(&monitor, start, 100);
+        }
+        let buckets: Vec<u32> = monitor.jitter.iter().map(|bucket| bucket.get()).collect();
+        assert_eq!(buckets, [1, 2, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(monitor.max_jitter.get(), 1_000);
+    }
+
+    #[test]
+    fn overrun_halts() {
+        static SAFE: SyncCell<bool> = SyncCell::new(false);
+        let monitor = CycleMonitor::new();
+        monitor.configure(1_000, OverrunReaction::Halt);
+        monitor.set_safe_state(|| SAFE.set(true));
+        run(&monitor, 0, 1_000);
+        assert!(!monitor.is_halted());
+        run(&monitor, 1_000, 1_001);
+        assert_eq!(monitor.overruns.get(), 1);
+        assert!(monitor.is_halted() && SAFE.get());
+    }
+}
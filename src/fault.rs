@@ -0,0 +1,244 @@
+//! Fault records, safe state outputs and an optional panic handler.
+//!
+//! With the `panic-handler` feature this crate provides the `#[panic_handler]`. On a
+//! panic it writes a `FaultRecord` to retained RAM, drives the registered outputs to
+//! their safe values, calls the panic hook and halts. Programs with their own panic
+//! handler can call `record_panic` instead.
+//!
+//! ```ignore
+//! static SAFE_OUTPUTS: [&dyn SafeState; 2] = [&SafeValue::new(&MOTOR, false), &SafeValue::new(&HEATER, 0)];
+//!
+//! // at boot
+//! fault::init();
+//! fault::set_safe_outputs(&SAFE_OUTPUTS);
+//! fault::set_panic_hook(|| LOG.save_to(&RETAINED_LOG));
+//! if let Some(fault) = fault::last_fault() { /* report */ }
+//! ```
+//!
+//! The fault record is placed in the `.uninit.pilot_sys.fault` section, which must not
+//! be initialized at startup (e.g. the `.uninit` sections of `cortex-m-rt`).
+
+use crate::cycle::CYCLE;
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{MemVar, Var, VarProps};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+const MAGIC: u32 = 0x4641_554c;
+
+/// Maximum length of the message in a fault record.
+pub const MESSAGE_LEN: usize = 96;
+/// Maximum length of the file name in a fault record, longer names keep their end.
+pub const FILE_LEN: usize = 48;
+
+/// Information about a fault, e.g. a panic.
+#[derive(Copy, Clone)]
+pub struct FaultRecord {
+    magic: u32,
+    pub line: u32,
+    pub column: u32,
+    /// value of the cycle counter `cycle::CYCLE.count`
+    pub cycle: u32,
+    /// system time in microseconds
+    pub timestamp: u64,
+    message_len: u8,
+    message: [u8; MESSAGE_LEN],
+    file_len: u8,
+    file: [u8; FILE_LEN],
+}
+
+impl FaultRecord {
+    const EMPTY: FaultRecord = FaultRecord {
+        magic: 0,
+        line: 0,
+        column: 0,
+        cycle: 0,
+        timestamp: 0,
+        message_len: 0,
+        message: [0; MESSAGE_LEN],
+        file_len: 0,
+        file: [0; FILE_LEN],
+    };
+
+    pub fn message(&self) -> &[u8] {
+        &self.message[..(self.message_len as usize).min(MESSAGE_LEN)]
+    }
+
+    pub fn file(&self) -> &[u8] {
+        &self.file[..(self.file_len as usize).min(FILE_LEN)]
+    }
+}
+
+impl Write for FaultRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.message_len as usize;
+        let count = s.len().min(MESSAGE_LEN - len);
+        self.message[len..len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.message_len += count as u8;
+        Ok(())
+    }
+}
+
+/// An output with a value it is driven to on a fault.
+pub trait SafeState: Sync {
+    fn apply(&self);
+}
+
+/// Sets a variable to its safe value, forcing is released.
+pub struct SafeValue<'a, T: Default> {
+    var: &'a Var<T>,
+    value: T,
+}
+
+impl<'a, T: Default> SafeValue<'a, T> {
+    pub const fn new(var: &'a Var<T>, value: T) -> Self {
+        SafeValue { var, value }
+    }
+}
+
+impl<T: Default + Copy + Sync> SafeState for SafeValue<'_, T>
+where
+    Var<T>: VarProps<T> + MemVar,
+{
+    fn apply(&self) {
+        unsafe { self.var.set_forced(0) };
+        self.var.set(self.value);
+    }
+}
+
+#[cfg_attr(not(feature = "std"), link_section = ".uninit.pilot_sys.fault")]
+static RETAINED: SyncCell<FaultRecord> = SyncCell::new(FaultRecord::EMPTY);
+
+static LAST: SyncCell<Option<FaultRecord>> = SyncCell::new(None);
+static SAFE_OUTPUTS: SyncCell<&'static [&'static dyn SafeState]> = SyncCell::new(&[]);
+static PANIC_HOOK: SyncCell<Option<fn()>> = SyncCell::new(None);
+
+/// true if the previous run ended with a fault, writing `FAULT` clears it
+pub static LAST_FAULT: Var<bool> = Var::<bool>::new();
+
+/// The fault record of the previous run for the host.
+pub static FAULT: FaultReport = FaultReport;
+
+/// Reads the fault record of the previous run from retained RAM,
+/// must be called once at boot.
+pub fn init() {
+    let record = RETAINED.get();
+    if record.magic == MAGIC {
+        LAST.set(Some(record));
+        LAST_FAULT.set(true);
+    }
+    RETAINED.set(FaultRecord::EMPTY);
+}
+
+/// returns the fault record of the previous run
+pub fn last_fault() -> Option<FaultRecord> {
+    LAST.get()
+}
+
+/// sets the outputs that are driven to their safe values on a fault
+pub fn set_safe_outputs(outputs: &'static [&'static dyn SafeState]) {
+    SAFE_OUTPUTS.set(outputs);
+}
+
+/// drives all registered outputs to their safe values,
+/// can also be used as `CycleMonitor::set_safe_state` hook
+pub fn apply_safe_state() {
+    for output in SAFE_OUTPUTS.get().iter() {
+        output.apply();
+    }
+}
+
+/// sets a hook that runs after the fault was recorded, e.g. to save the log or reset
+pub fn set_panic_hook(hook: fn()) {
+    PANIC_HOOK.set(Some(hook));
+}
+
+/// Writes a fault record to retained RAM.
+pub fn record_fault(message: fmt::Arguments, file: &str, line: u32, column: u32) {
+    let mut record = FaultRecord {
+        magic: MAGIC,
+        line,
+        column,
+        cycle: CYCLE.count.get(),
+        timestamp: current_time(),
+        ..FaultRecord::EMPTY
+    };
+    let _ = record.write_fmt(message);
+    let file = &file.as_bytes()[file.len().saturating_sub(FILE_LEN)..];
+    record.file[..file.len()].copy_from_slice(file);
+    record.file_len = file.len() as u8;
+    RETAINED.set(record);
+}
+
+/// Records a panic, drives the outputs to their safe values and calls the panic hook.
+pub fn record_panic(info: &PanicInfo) {
+    let (file, line, column) = match info.location() {
+        Some(location) => (location.file(), location.line(), location.column()),
+        None => ("", 0, 0),
+    };
+    record_fault(format_args!("{}", info.message()), file, line, column);
+    apply_safe_state();
+    if let Some(hook) = PANIC_HOOK.get() {
+        hook();
+    }
+}
+
+#[cfg(all(feature = "panic-handler", not(test), not(feature = "std")))]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    record_panic(info);
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Host access to the fault record of the previous run.
+pub struct FaultReport;
+
+impl MemVar for FaultReport {
+    /// subvalue 0 writes line, column, cycle (u32 each) and timestamp (u64),
+    /// 1 the message and 2 the file name
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        let record = LAST.get().unwrap_or(FaultRecord::EMPTY);
+        let bytes: &[u8] = match subvalue {
+            0 => {
+                let header = [record.line, record.column, record.cycle];
+                for (i, value) in header.iter().enumerate() {
+                    (buffer.add(i * 4) as *mut u32).write_unaligned(*value);
+                }
+                (buffer.add(12) as *mut u64).write_unaligned(record.timestamp);
+                return 20;
+            }
+            1 => record.message(),
+            _ => record.file(),
+        };
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+        bytes.len() as u8
+    }
+
+    /// acknowledges the fault report
+    unsafe fn from_buffer(&self, _buffer: *const u8, _subvalue: u8) -> u8 {
+        LAST.set(None);
+        LAST_FAULT.set(false);
+        0
+    }
+
+    unsafe fn is_dirty(&self) -> bool {
+        LAST_FAULT.get()
+    }
+
+    unsafe fn clear_dirty(&self) {}
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_subscribed(&self, _value: u8) {}
+}
@@ -0,0 +1,643 @@
+//! Signal processing blocks: moving average, low-pass, median, rate limiter, ramp and
+//! integrator.
+//!
+//! All blocks are generic over the `Sample` type. Floating point samples (`f32`, `f64`)
+//! are processed as is, integer samples (`i16`, `u16`, `i32`) with a 48.16 fixed-point
+//! state, so the blocks also work on MCUs without an FPU.
+//!
+//! ```ignore
+//! static AI_FILTER: LowPass<i16> = LowPass::new(200_000);
+//! static SPEED_RAMP: Ramp<f32> = Ramp::new(500.0, 1000.0);
+//!
+//! // cyclic, from a loop_async! body
+//! AI_FILTER.cycle(&AI_RAW, &AI_FILTERED);
+//! // or as a task of its own
+//! SPEED_RAMP.run(&SPEED_SETPOINT, &SPEED_OUT).await;
+//! ```
+//!
+//! Time dependent blocks take the time from `time::current_time()` and ignore further
+//! updates within the same cycle. Rates are given in units per second.
+
+use crate::sync::SyncCell;
+use crate::time::{current_time, wait_next_cycle};
+use crate::var::VarProps;
+use core::future::Future;
+use core::ops::{Add, Neg, Sub};
+
+/// A sample type the blocks can process.
+pub trait Sample: Copy + PartialOrd + Sync + 'static {
+    /// type of the internal state
+    type Acc: Copy + PartialOrd + Sync + Add<Output = Self::Acc> + Sub<Output = Self::Acc> + Neg<Output = Self::Acc>;
+
+    const ZERO: Self;
+    const ACC_ZERO: Self::Acc;
+
+    fn to_acc(self) -> Self::Acc;
+    /// converts back with rounding, saturates at the range of the type
+    fn from_acc(acc: Self::Acc) -> Self;
+    /// returns `a * b / c`
+    fn mul_div(a: Self::Acc, b: Self::Acc, c: Self::Acc) -> Self::Acc;
+    /// returns `a * num / den`
+    fn scale(a: Self::Acc, num: u64, den: u64) -> Self::Acc;
+}
+
+macro_rules! float_sample {
+    ($t:ty) => {
+        impl Sample for $t {
+            type Acc = $t;
+
+            const ZERO: $t = 0.0;
+            const ACC_ZERO: $t = 0.0;
+
+            fn to_acc(self) -> $t {
+                self
+            }
+
+            fn from_acc(acc: $t) -> $t {
+                acc
+            }
+
+            fn mul_div(a: $t, b: $t, c: $t) -> $t {
+                a * b / c
+            }
+
+            fn scale(a: $t, num: u64, den: u64) -> $t {
+                a * num as $t / den as $t
+            }
+        }
+    };
+}
+
+macro_rules! fixed_sample {
+    ($t:ty) => {
+        impl Sample for $t {
+            type Acc = i64;
+
+            const ZERO: $t = 0;
+            const ACC_ZERO: i64 = 0;
+
+            fn to_acc(self) -> i64 {
+                (self as i64) << 16
+            }
+
+            fn from_acc(acc: i64) -> $t {
+                ((acc + (1 << 15)) >> 16).clamp(<$t>::MIN as i64, <$t>::MAX as i64) as $t
+            }
+
+            fn mul_div(a: i64, b: i64, c: i64) -> i64 {
+                match c {
+                    0 => 0,
+                    c => (a as i128 * b as i128 / c as i128) as i64,
+                }
+            }
+
+            fn scale(a: i64, num: u64, den: u64) -> i64 {
+                match den {
+                    0 => 0,
+                    den => (a as i128 * num as i128 / den as i128) as i64,
+                }
+            }
+        }
+    };
+}
+
+float_sample!(f32);
+float_sample!(f64);
+fixed_sample!(i16);
+fixed_sample!(u16);
+fixed_sample!(i32);
+
+fn abs<T: Sample>(value: T::Acc) -> T::Acc {
+    match value < T::ACC_ZERO {
+        true => -value,
+        false => value,
+    }
+}
+
+/// A block that turns an input signal into an output signal.
+pub trait Filter {
+    type Input: Copy;
+    type Output: Copy;
+
+    /// processes the next input value and returns the output
+    fn update(&self, input: Self::Input) -> Self::Output;
+
+    /// reads `input`, updates the block and writes `output`
+    fn cycle<I, O>(&self, input: &I, output: &O)
+    where
+        I: VarProps<Self::Input>,
+        O: VarProps<Self::Output>,
+    {
+        output.set(self.update(input.get()));
+    }
+
+    /// updates the block in every cycle
+    fn run<'a, I, O>(&'a self, input: &'a I, output: &'a O) -> impl Future<Output = ()> + 'a
+    where
+        Self: Sized,
+        I: VarProps<Self::Input>,
+        O: VarProps<Self::Output>,
+    {
+        async move {
+            loop {
+                self.cycle(input, output);
+                wait_next_cycle().await;
+            }
+        }
+    }
+}
+
+/// Time since the last update of a block.
+struct Elapsed(SyncCell<Option<u64>>);
+
+impl Elapsed {
+    const fn new() -> Elapsed {
+        Elapsed(SyncCell::new(None))
+    }
+
+    /// returns the microseconds since the last call, `Some(0)` on the first call and
+    /// after the clock stepped back, and `None` if it was already called in this cycle
+    fn take(&self) -> Option<u64> {
+        let now = current_time();
+        let elapsed = match self.0.get() {
+            Some(last) if now == last => return None,
+            Some(last) => now.saturating_sub(last),
+            None => 0,
+        };
+        self.0.set(Some(now));
+        Some(elapsed)
+    }
+
+    fn reset(&self) {
+        self.0.set(None);
+    }
+}
+
+/// Average of the last `N` samples.
+pub struct MovingAverage<T: Sample, const N: usize> {
+    samples: [SyncCell<T>; N],
+    next: SyncCell<usize>,
+    count: SyncCell<usize>,
+}
+
+impl<T: Sample, const N: usize> MovingAverage<T, N> {
+    pub const fn new() -> Self {
+        assert!(N > 0);
+        MovingAverage {
+            samples: [const { SyncCell::new(T::ZERO) }; N],
+            next: SyncCell::new(0),
+            count: SyncCell::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.next.set(0);
+        self.count.set(0);
+    }
+}
+
+impl<T: Sample, const N: usize> Default for MovingAverage<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Sample, const N: usize> Filter for MovingAverage<T, N> {
+    type Input = T;
+    type Output = T;
+
+    /// averages over the samples received so far until the window is full
+    fn update(&self, input: T) -> T {
+        let next = self.next.get();
+        self.samples[next].set(input);
+        self.next.set((next + 1) % N);
+        let count = (self.count.get() + 1).min(N);
+        self.count.set(count);
+        let sum = self.samples[..count]
+            .iter()
+            .fold(T::ACC_ZERO, |sum, sample| sum + sample.get().to_acc());
+        T::from_acc(T::scale(sum, 1, count as u64))
+    }
+}
+
+/// First-order low-pass filter.
+pub struct LowPass<T: Sample> {
+    time_constant_us: SyncCell<u32>,
+    state: SyncCell<Option<T::Acc>>,
+    elapsed: Elapsed,
+}
+
+impl<T: Sample> LowPass<T> {
+    pub const fn new(time_constant_us: u32) -> Self {
+        LowPass {
+            time_constant_us: SyncCell::new(time_constant_us),
+            state: SyncCell::new(None),
+            elapsed: Elapsed::new(),
+        }
+    }
+
+    pub fn time_constant(&self) -> u32 {
+        self.time_constant_us.get()
+    }
+
+    pub fn set_time_constant(&self, time_constant_us: u32) {
+        self.time_constant_us.set(time_constant_us);
+    }
+
+    /// the next update starts at the input value
+    pub fn reset(&self) {
+        self.state.set(None);
+        self.elapsed.reset();
+    }
+}
+
+impl<T: Sample> Filter for LowPass<T> {
+    type Input = T;
+    type Output = T;
+
+    fn update(&self, input: T) -> T {
+        let input = input.to_acc();
+        let state = match (self.state.get(), self.elapsed.take()) {
+            (None, _) => input,
+            (Some(state), None) => state,
+            (Some(state), Some(dt)) => {
+                let tau = self.time_constant_us.get() as u64;
+                state + T::scale(input - state, dt, tau + dt.max(1))
+            }
+        };
+        self.state.set(Some(state));
+        T::from_acc(state)
+    }
+}
+
+/// Median of the last `N` samples, removes single spikes.
+pub struct Median<T: Sample, const N: usize> {
+    samples: [SyncCell<T>; N],
+    next: SyncCell<usize>,
+    count: SyncCell<usize>,
+}
+
+impl<T: Sample, const N: usize> Median<T, N> {
+    pub const fn new() -> Self {
+        assert!(N > 0);
+        Median {
+            samples: [const { SyncCell::new(T::ZERO) }; N],
+            next: SyncCell::new(0),
+            count: SyncCell::new(0),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.next.set(0);
+        self.count.set(0);
+    }
+}
+
+impl<T: Sample, const N: usize> Default for Median<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Sample, const N: usize> Filter for Median<T, N> {
+    type Input = T;
+    type Output = T;
+
+    /// for an even number of samples the lower of the two middle samples is returned
+    fn update(&self, input: T) -> T {
+        let next = self.next.get();
+        self.samples[next].set(input);
+        self.next.set((next + 1) % N);
+        let count = (self.count.get() + 1).min(N);
+        self.count.set(count);
+
+        let mut sorted = [T::ZERO; N];
+        for i in 0..count {
+            let sample = self.samples[i].get();
+            let mut j = i;
+            while j > 0 && sorted[j - 1] > sample {
+                sorted[j] = sorted[j - 1];
+                j -= 1;
+            }
+            sorted[j] = sample;
+        }
+        sorted[(count - 1) / 2]
+    }
+}
+
+/// Limits the rate of change of a signal, separately for rising and falling values.
+pub struct RateLimiter<T: Sample> {
+    rise: SyncCell<T>,
+    fall: SyncCell<T>,
+    state: SyncCell<Option<T::Acc>>,
+    elapsed: Elapsed,
+}
+
+impl<T: Sample> RateLimiter<T> {
+    /// `rise` and `fall` are the maximum rates in units per second (positive values)
+    pub const fn new(rise: T, fall: T) -> Self {
+        RateLimiter {
+            rise: SyncCell::new(rise),
+            fall: SyncCell::new(fall),
+            state: SyncCell::new(None),
+            elapsed: Elapsed::new(),
+        }
+    }
+
+    pub fn set_rates(&self, rise: T, fall: T) {
+        self.rise.set(rise);
+        self.fall.set(fall);
+    }
+
+    /// the next update starts at the input value
+    pub fn reset(&self) {
+        self.state.set(None);
+        self.elapsed.reset();
+    }
+}
+
+impl<T: Sample> Filter for RateLimiter<T> {
+    type Input = T;
+    type Output = T;
+
+    fn update(&self, input: T) -> T {
+        let input = input.to_acc();
+        let state = match (self.state.get(), self.elapsed.take()) {
+            (None, _) => input,
+            (Some(state), None) => state,
+            (Some(state), Some(dt)) => {
+                let max_rise = T::scale(self.rise.get().to_acc(), dt, 1_000_000);
+                let max_fall = T::scale(self.fall.get().to_acc(), dt, 1_000_000);
+                match input - state {
+                    delta if delta > max_rise => state + max_rise,
+                    delta if delta < -max_fall => state - max_fall,
+                    _ => input,
+                }
+            }
+        };
+        self.state.set(Some(state));
+        T::from_acc(state)
+    }
+}
+
+/// Moves the output to the setpoint with limited speed and acceleration.
+pub struct Ramp<T: Sample> {
+    max_rate: SyncCell<T>,
+    max_accel: SyncCell<T>,
+    position: SyncCell<Option<T::Acc>>,
+    velocity: SyncCell<T::Acc>,
+    elapsed: Elapsed,
+}
+
+impl<T: Sample> Ramp<T> {
+    /// `max_rate` in units per second, `max_accel` in units per second²,
+    /// 0 disables the acceleration limit
+    pub const fn new(max_rate: T, max_accel: T) -> Self {
+        Ramp {
+            max_rate: SyncCell::new(max_rate),
+            max_accel: SyncCell::new(max_accel),
+            position: SyncCell::new(None),
+            velocity: SyncCell::new(T::ACC_ZERO),
+            elapsed: Elapsed::new(),
+        }
+    }
+
+    pub fn set_limits(&self, max_rate: T, max_accel: T) {
+        self.max_rate.set(max_rate);
+        self.max_accel.set(max_accel);
+    }
+
+    /// sets the output without ramping, e.g. to the actual value when taking over
+    pub fn set_position(&self, position: T) {
+        self.position.set(Some(position.to_acc()));
+        self.velocity.set(T::ACC_ZERO);
+    }
+
+    /// returns true if the output reached the setpoint
+    pub fn is_done(&self, setpoint: T) -> bool {
+        self.position.get() == Some(setpoint.to_acc()) && self.velocity.get() == T::ACC_ZERO
+    }
+
+    /// the next update starts at the setpoint
+    pub fn reset(&self) {
+        self.position.set(None);
+        self.velocity.set(T::ACC_ZERO);
+        self.elapsed.reset();
+    }
+
+    fn step(&self, position: T::Acc, target: T::Acc, dt: u64) -> T::Acc {
+        let zero = T::ACC_ZERO;
+        let max_rate = self.max_rate.get().to_acc();
+        let accel = self.max_accel.get().to_acc();
+        let distance = target - position;
+        let rate = match distance < zero {
+            true => -max_rate,
+            false => max_rate,
+        };
+        let velocity = match accel > zero {
+            false => rate,
+            true => {
+                let velocity = self.velocity.get();
+                let dv = T::scale(accel, dt, 1_000_000);
+                // brake if the stopping distance v² / 2a reaches the setpoint
+                let towards = (velocity > zero) == (distance > zero);
+                let stopping = T::mul_div(velocity, velocity, accel + accel);
+                let goal = match towards && stopping >= abs::<T>(distance) {
+                    true => zero,
+                    false => rate,
+                };
+                match goal - velocity {
+                    diff if diff > dv => velocity + dv,
+                    diff if diff < -dv => velocity - dv,
+                    _ => goal,
+                }
+            }
+        };
+        let next = position + T::scale(velocity, dt, 1_000_000);
+        // stop at the setpoint instead of overshooting it
+        if distance == zero || (target - next < zero) != (distance < zero) {
+            self.velocity.set(zero);
+            return target;
+        }
+        self.velocity.set(velocity);
+        next
+    }
+}
+
+impl<T: Sample> Filter for Ramp<T> {
+    /// setpoint
+    type Input = T;
+    type Output = T;
+
+    fn update(&self, setpoint: T) -> T {
+        let target = setpoint.to_acc();
+        let position = match (self.position.get(), self.elapsed.take()) {
+            (None, _) => target,
+            (Some(position), None) => position,
+            (Some(position), Some(dt)) => self.step(position, target, dt),
+        };
+        self.position.set(Some(position));
+        T::from_acc(position)
+    }
+}
+
+/// Integrates the input over time, e.g. a flow rate in units per second to a total.
+pub struct Integrator<T: Sample> {
+    total: SyncCell<T::Acc>,
+    hold: SyncCell<bool>,
+    elapsed: Elapsed,
+}
+
+impl<T: Sample> Integrator<T> {
+    pub const fn new() -> Self {
+        Integrator {
+            total: SyncCell::new(T::ACC_ZERO),
+            hold: SyncCell::new(false),
+            elapsed: Elapsed::new(),
+        }
+    }
+
+    pub fn total(&self) -> T {
+        T::from_acc(self.total.get())
+    }
+
+    pub fn set_total(&self, total: T) {
+        self.total.set(total.to_acc());
+    }
+
+    /// stops integrating while `hold` is set
+    pub fn set_hold(&self, hold: bool) {
+        self.hold.set(hold);
+    }
+
+    pub fn reset(&self) {
+        self.total.set(T::ACC_ZERO);
+        self.elapsed.reset();
+    }
+}
+
+impl<T: Sample> Default for Integrator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Sample> Filter for Integrator<T> {
+    /// rate in units per second
+    type Input = T;
+    /// total
+    type Output = T;
+
+    fn update(&self, rate: T) -> T {
+        if let Some(dt) = self.elapsed.take() {
+            if !self.hold.get() {
+                self.total.set(self.total.get() + T::scale(rate.to_acc(), dt, 1_000_000));
+            }
+        }
+        self.total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{lock_time, set_system_time};
+    use core::fmt::Debug;
+
+    trait TestSample: Sample + Debug {
+        fn of(value: i32) -> Self;
+        fn value(self) -> f64;
+    }
+
+    macro_rules! test_sample {
+        ($($t:ty),*) => {$(
+            impl TestSample for $t {
+                fn of(value: i32) -> $t {
+                    value as $t
+                }
+
+                fn value(self) -> f64 {
+                    self as f64
+                }
+            }
+        )*};
+    }
+
+    test_sample!(f32, f64, i16, u16, i32);
+
+    /// feeds `inputs` one second apart and compares the outputs, integer types round
+    fn check<T: TestSample>(filter: &impl Filter<Input = T, Output = T>, inputs: &[i32], outputs: &[f64]) {
+        for (second, (input, expected)) in inputs.iter().zip(outputs).enumerate() {
+            set_system_time(second as u64 * 1_000_000);
+            let output = filter.update(T::of(*input)).value();
+            assert!((output - expected).abs() <= 0.5, "{} s: {} != {}", second, output, expected);
+        }
+    }
+
+    fn step_responses<T: TestSample>() {
+        check(&MovingAverage::<T, 4>::new(), &[0, 0, 0, 0, 100, 100, 100, 100], &[0., 0., 0., 0., 25., 50., 75., 100.]);
+        check(&LowPass::<T>::new(1_000_000), &[0, 100, 100, 100], &[0., 50., 75., 87.5]);
+        check(&Median::<T, 3>::new(), &[10, 100, 10, 20, 30], &[10., 10., 10., 20., 20.]);
+        let limiter = RateLimiter::new(T::of(10), T::of(20));
+        check(&limiter, &[0, 100, 100, 0, 0], &[0., 10., 20., 0., 0.]);
+        let ramp = Ramp::new(T::of(10), T::ZERO);
+        check(&ramp, &[0, 25, 25, 25, 25], &[0., 10., 20., 25., 25.]);
+        assert!(ramp.is_done(T::of(25)));
+        let ramp = Ramp::new(T::of(10), T::of(5));
+        check(&ramp, &[0, 100, 100, 100], &[0., 5., 15., 25.]);
+        let integrator = Integrator::new();
+        check(&integrator, &[5, 5, 5, 5], &[0., 5., 10., 15.]);
+        integrator.set_hold(true);
+        set_system_time(5_000_000);
+        assert_eq!(integrator.update(T::of(5)).value(), 15.0);
+    }
+
+    #[test]
+    fn step_response_per_type() {
+        let _time = lock_time();
+        step_responses::<f32>();
+        step_responses::<f64>();
+        step_responses::<i16>();
+        step_responses::<u16>();
+        step_responses::<i32>();
+    }
+
+    #[test]
+    fn fixed_point_saturates() {
+        let _time = lock_time();
+        fn saturate<T: TestSample>(rate: T, max: T) {
+            let integrator = Integrator::new();
+            for second in 0..3 {
+                set_system_time(second * 1_000_000);
+                integrator.update(rate);
+            }
+            assert_eq!(integrator.total().value(), max.value());
+            let low_pass = LowPass::new(1_000_000);
+            set_system_time(0);
+            low_pass.update(max);
+            set_system_time(1_000_000);
+            assert_eq!(low_pass.update(max).value(), max.value());
+        }
+        saturate(30_000i16, i16::MAX);
+        saturate(40_000u16, u16::MAX);
+        saturate(i32::MAX, i32::MAX);
+        // the clamp also rounds to the nearest value
+        assert_eq!(i16::from_acc(-(40_000 << 16)), i16::MIN);
+        assert_eq!(u16::from_acc(-(1 << 16)), 0);
+        assert_eq!(i32::from_acc(3 << 15), 2);
+    }
+
+    #[test]
+    fn clock_stepped_back() {
+        let _time = lock_time();
+        let low_pass = LowPass::<f32>::new(1_000_000);
+        set_system_time(10_000_000);
+        low_pass.update(0.0);
+        // the same cycle keeps the state, an earlier time restarts the interval
+        assert_eq!(low_pass.update(100.0), 0.0);
+        set_system_time(0);
+        assert_eq!(low_pass.update(100.0), 0.0);
+        set_system_time(1_000_000);
+        assert_eq!(low_pass.update(100.0), 50.0);
+    }
+}
@@ -0,0 +1,262 @@
+//! Variable groups the host reads and writes as a whole.
+//!
+//! ```ignore
+//! static POSITION: VarGroup<'static, 3> = VarGroup::new([&POS_X, &POS_Y, &POS_Z]);
+//!
+//! // runtime, every cycle
+//! POSITION.commit(); // apply host writes before the tasks run
+//! poll_tasks();
+//! POSITION.snapshot(); // consistent values for the host after the tasks ran
+//! ```
+//!
+//! The host reads the snapshot of all variables with one `to_buffer` call and writes
+//! all of them with one `from_buffer` call, the values are packed in the order of the
+//! variables. Writes are staged and applied together with the next `commit`. The group
+//! reads as empty until the runtime took the first snapshot.
+
+use crate::sync::SyncCell;
+use crate::var::{MemVar, VarBuffer};
+
+/// Maximum size of a variable in a group.
+pub const MAX_VALUE: usize = 8;
+
+#[derive(Copy, Clone, PartialEq)]
+struct Raw {
+    len: u8,
+    bytes: [u8; MAX_VALUE],
+}
+
+impl Raw {
+    const EMPTY: Raw = Raw {
+        len: 0,
+        bytes: [0; MAX_VALUE],
+    };
+
+    fn read(var: &dyn MemVar) -> Raw {
+        let mut buffer = VarBuffer::new();
+        let len = (unsafe { var.to_buffer(buffer.as_mut_ptr(), 0) } as usize).min(MAX_VALUE);
+        let mut raw = Raw {
+            len: len as u8,
+            ..Raw::EMPTY
+        };
+        raw.bytes[..len].copy_from_slice(&buffer.0[..len]);
+        raw
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// A group of up to 31 variables of up to 8 bytes each.
+pub struct VarGroup<'a, const N: usize> {
+    vars: [&'a dyn MemVar; N],
+    snapshot: [SyncCell<Option<Raw>>; N],
+    staged: [SyncCell<Option<Raw>>; N],
+    dirty: SyncCell<bool>,
+    subscribed: SyncCell<u8>,
+}
+
+impl<'a, const N: usize> VarGroup<'a, N> {
+    pub const fn new(vars: [&'a dyn MemVar; N]) -> Self {
+        // all values must fit into a MemVar buffer
+        assert!(N > 0 && N * MAX_VALUE <= u8::MAX as usize);
+        VarGroup {
+            vars,
+            snapshot: [const { SyncCell::new(None) }; N],
+            staged: [const { SyncCell::new(None) }; N],
+            dirty: SyncCell::new(false),
+            subscribed: SyncCell::new(0),
+        }
+    }
+
+    /// Takes a snapshot of all variables, call it at the end of the cycle. The group
+    /// becomes dirty if a value changed and the host subscribed to the group.
+    pub fn snapshot(&self) {
+        let mut changed = false;
+        for (var, snapshot) in self.vars.iter().zip(self.snapshot.iter()) {
+            let raw = Some(Raw::read(*var));
+            if raw != snapshot.get() {
+                snapshot.set(raw);
+                changed = true;
+            }
+        }
+        if changed && self.subscribed.get() != 0 {
+            self.dirty.set(true);
+        }
+    }
+
+    /// Applies all staged writes together, call it at the start of the cycle.
+    pub fn commit(&self) {
+        for (var, staged) in self.vars.iter().zip(self.staged.iter()) {
+            if let Some(raw) = staged.take() {
+                let mut buffer = VarBuffer::new();
+                buffer.0[..raw.len as usize].copy_from_slice(raw.as_bytes());
+                unsafe { var.from_buffer(buffer.as_ptr(), 0) };
+            }
+        }
+    }
+
+    /// returns true if there are staged writes
+    pub fn is_pending(&self) -> bool {
+        self.staged.iter().any(|staged| staged.get().is_some())
+    }
+
+    /// returns the size of variable `index`, from the snapshot if there is one
+    fn size(&self, index: usize) -> usize {
+        match self.snapshot[index].get() {
+            Some(raw) => raw.len as usize,
+            None => Raw::read(self.vars[index]).len as usize,
+        }
+    }
+
+    /// stages the value of variable `index` from `bytes`, returns the number of bytes used
+    fn stage(&self, index: usize, bytes: *const u8) -> usize {
+        let len = self.size(index);
+        let mut raw = Raw {
+            len: len as u8,
+            ..Raw::EMPTY
+        };
+        unsafe { core::ptr::copy_nonoverlapping(bytes, raw.bytes.as_mut_ptr(), len) };
+        self.staged[index].set(Some(raw));
+        len
+    }
+}
+
+impl<const N: usize> MemVar for VarGroup<'_, N> {
+    /// subvalue 0 writes the snapshot of all variables, subvalue `1 + i` the snapshot
+    /// of variable `i`, nothing until the runtime took the first snapshot
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        // the host must not take a snapshot in the middle of a cycle
+        if self.snapshot.iter().any(|snapshot| snapshot.get().is_none()) {
+            return 0;
+        }
+        let range = match subvalue as usize {
+            0 => 0..N,
+            index if index <= N => index - 1..index,
+            _ => return 0,
+        };
+        let mut len = 0;
+        for snapshot in self.snapshot[range].iter() {
+            let raw = snapshot.get().unwrap_or(Raw::EMPTY);
+            core::ptr::copy_nonoverlapping(raw.bytes.as_ptr(), buffer.add(len), raw.len as usize);
+            len += raw.len as usize;
+        }
+        len as u8
+    }
+
+    /// subvalue 0 stages the values of all variables, subvalue `1 + i` the value of
+    /// variable `i`, they are applied with the next `commit`
+    unsafe fn from_buffer(&self, buffer: *const u8, subvalue: u8) -> u8 {
+        let range = match subvalue as usize {
+            0 => 0..N,
+            index if index <= N => index - 1..index,
+            _ => return 0,
+        };
+        let mut len = 0;
+        for index in range {
+            len += self.stage(index, buffer.add(len));
+        }
+        len as u8
+    }
+
+    /// true if a value changed since the host read the group
+    unsafe fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    unsafe fn clear_dirty(&self) {
+        self.dirty.set(false);
+    }
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    /// groups cannot be forced, force the variables instead
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        self.subscribed.get()
+    }
+
+    unsafe fn set_subscribed(&self, value: u8) {
+        self.subscribed.set(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::var::{SubscribeMode, Var, VarProps};
+
+    fn read(group: &VarGroup<2>, subvalue: u8) -> Vec<u8> {
+        let mut buffer = VarBuffer::new();
+        let len = unsafe { group.to_buffer(buffer.as_mut_ptr(), subvalue) } as usize;
+        buffer.0[..len].to_vec()
+    }
+
+    #[test]
+    fn consistent_snapshot() {
+        let x = Var::<u16>::new();
+        let y = Var::<u32>::new();
+        let group = VarGroup::new([&x, &y]);
+        x.set(1);
+        assert!(read(&group, 0).is_empty());
+
+        group.snapshot();
+        x.set(2);
+        y.set(3);
+        // the host sees the values of the last snapshot only
+        assert_eq!(read(&group, 0), [1, 0, 0, 0, 0, 0]);
+        group.snapshot();
+        assert_eq!(read(&group, 0), [2, 0, 3, 0, 0, 0]);
+        assert_eq!(read(&group, 1), [2, 0]);
+        assert_eq!(read(&group, 2), [3, 0, 0, 0]);
+        assert!(read(&group, 3).is_empty());
+    }
+
+    #[test]
+    fn staged_writes() {
+        let x = Var::<u16>::new();
+        let y = Var::<u32>::new();
+        let group = VarGroup::new([&x, &y]);
+        group.snapshot();
+        unsafe {
+            assert_eq!(group.from_buffer([5, 0, 6, 0, 0, 0].as_ptr(), 0), 6);
+        }
+        assert!(group.is_pending());
+        assert_eq!((x.get(), y.get()), (0, 0));
+        group.commit();
+        assert_eq!((x.get(), y.get()), (5, 6));
+        assert!(!group.is_pending());
+
+        unsafe {
+            assert_eq!(group.from_buffer([7, 0, 0, 0].as_ptr(), 2), 4);
+            assert_eq!(group.from_buffer([9].as_ptr(), 3), 0);
+        }
+        group.commit();
+        assert_eq!((x.get(), y.get()), (5, 7));
+    }
+
+    #[test]
+    fn dirty_only_when_subscribed() {
+        let x = Var::<u16>::new();
+        let y = Var::<u32>::new();
+        let group = VarGroup::new([&x, &y]);
+        group.snapshot();
+        x.set(1);
+        group.snapshot();
+        unsafe {
+            assert!(!group.is_dirty());
+            group.set_subscribed(SubscribeMode::Current as u8);
+            group.snapshot();
+            assert!(!group.is_dirty());
+            y.set(1);
+            group.snapshot();
+            assert!(group.is_dirty());
+            group.clear_dirty();
+            assert!(!group.is_dirty());
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Hierarchical state machines with async entry, exit and do actions.
+//!
+//! States are described by a type implementing `StateId`, usually an enum. The behaviour
+//! is implemented by a `Machine`, and an `Hsm` runs it and exposes the current state
+//! and the transition history.
+//!
+//! ```ignore
+//! #[derive(Copy, Clone, Eq, PartialEq)]
+//! enum Door { Closed, Opening, Open }
+//!
+//! impl StateId for Door {
+//!     fn id(self) -> u16 { self as u16 }
+//! }
+//!
+//! struct DoorMachine;
+//!
+//! impl Machine for DoorMachine {
+//!     type State = Door;
+//!
+//!     fn entry(&self, state: Door) -> impl Future<Output = ()> {
+//!         async move { MOTOR.set(state == Door::Opening) }
+//!     }
+//!
+//!     fn transitions(&self, state: Door) -> impl Future<Output = Door> {
+//!         async move {
+//!             match state {
+//!                 Door::Closed => { OPEN_CMD.pos().await; Door::Opening }
+//!                 Door::Opening => match END_SWITCH.pos().timeout(Duration::from_secs(10)).await {
+//!                     Ok(()) => Door::Open,
+//!                     Err(Timeout) => Door::Closed,
+//!                 },
+//!                 Door::Open => { wait(Duration::from_secs(30)).await; Door::Closed }
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! static DOOR: Hsm<16> = Hsm::new();
+//! DOOR.run(&DoorMachine, Door::Closed).await;
+//! ```
+
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{Var, VarProps};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Poll;
+use futures::future;
+
+/// Maximum nesting depth of states.
+pub const MAX_DEPTH: usize = 8;
+
+/// Identifies a state and its position in the state hierarchy.
+pub trait StateId: Copy + Eq {
+    /// numeric id of the state, exposed to the host in `Hsm::state`
+    fn id(self) -> u16;
+
+    /// returns the enclosing state, `None` for top level states
+    fn parent(self) -> Option<Self> {
+        None
+    }
+
+    /// returns the sub state that is entered when a transition targets this state
+    fn initial(self) -> Option<Self> {
+        None
+    }
+}
+
+/// The behaviour of a state machine.
+pub trait Machine {
+    type State: StateId;
+
+    /// runs when a state is entered, parents are entered before their sub states
+    fn entry(&self, _state: Self::State) -> impl Future<Output = ()> {
+        async {}
+    }
+
+    /// runs when a state is left, sub states are left before their parents
+    fn exit(&self, _state: Self::State) -> impl Future<Output = ()> {
+        async {}
+    }
+
+    /// runs while the state or one of its sub states is active,
+    /// it is dropped as soon as a transition fires
+    fn action(&self, _state: Self::State) -> impl Future<Output = ()> {
+        future::pending()
+    }
+
+    /// Waits for the guards of the outgoing transitions of `state` and returns the
+    /// target state of the transition that fired.
+    ///
+    /// The transitions of all active states are awaited in parallel, sub states take
+    /// precedence over their parents. States without outgoing transitions should
+    /// return a future that never finishes, e.g. `future::pending()`.
+    fn transitions(&self, state: Self::State) -> impl Future<Output = Self::State>;
+}
+
+/// An entry of the transition history.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Transition {
+    /// id of the state that was left
+    pub from: u16,
+    /// id of the state that was entered
+    pub to: u16,
+    /// system time of the transition in microseconds
+    pub time: u64,
+}
+
+/// Runs a `Machine` and records its transitions, keeps the last `H` transitions.
+pub struct Hsm<const H: usize> {
+    /// id of the active (innermost) state
+    pub state: Var<u16>,
+    /// number of transitions since start, wraps around
+    pub transitions: Var<u32>,
+    history: [SyncCell<Transition>; H],
+}
+
+type Path<S> = [Option<S>; MAX_DEPTH];
+
+/// returns the state and its parents, innermost state first
+fn path<S: StateId>(state: S) -> Path<S> {
+    let mut path = [None; MAX_DEPTH];
+    let mut next = Some(state);
+    for entry in path.iter_mut() {
+        *entry = next;
+        next = next.and_then(|s| s.parent());
+    }
+    path
+}
+
+/// follows the initial sub states down to the innermost state
+fn innermost<S: StateId>(mut state: S) -> S {
+    for _ in 0..MAX_DEPTH {
+        match state.initial() {
+            Some(sub) => state = sub,
+            None => break,
+        }
+    }
+    state
+}
+
+/// Pins the element at `index` of a pinned array of optional futures.
+fn pin_index<F, const N: usize>(futures: Pin<&mut [Option<F>; N]>, index: usize) -> Pin<&mut Option<F>> {
+    // SAFETY: the array is pinned and its elements are never moved out of it
+    unsafe { futures.map_unchecked_mut(|futures| &mut futures[index]) }
+}
+
+impl<const H: usize> Hsm<H> {
+    pub const fn new() -> Self {
+        Hsm {
+            state: Var::<u16>::new(),
+            transitions: Var::<u32>::new(),
+            history: [const {
+                SyncCell::new(Transition {
+                    from: 0,
+                    to: 0,
+                    time: 0,
+                })
+            }; H],
+        }
+    }
+
+    /// Returns a recorded transition, `0` is the most recent one.
+    pub fn history(&self, index: usize) -> Option<Transition> {
+        let count = self.transitions.get() as usize;
+        if index >= H || index >= count {
+            return None;
+        }
+        Some(self.history[(count - 1 - index) % H].get())
+    }
+
+    fn record(&self, from: u16, to: u16) {
+        let count = self.transitions.get();
+        if H > 0 {
+            self.history[count as usize % H].set(Transition {
+                from,
+                to,
+                time: current_time(),
+            });
+        }
+        self.transitions.set(count.wrapping_add(1));
+    }
+
+    /// Runs the machine forever, starting in `initial`.
+    pub async fn run<M: Machine>(&self, machine: &M, initial: M::State) {
+        let mut current = innermost(initial);
+        for state in path(current).iter().rev().flatten() {
+            machine.entry(*state).await;
+        }
+        self.state.set(current.id());
+
+        loop {
+            let target = Self::next_transition(machine, current).await;
+            let next = innermost(target);
+            let from = path(current);
+            let to = path(next);
+
+            // a transition to the current state or one of its parents leaves and
+            // re-enters the target, otherwise only the states below the common
+            // ancestor are left and entered
+            let common = if from.contains(&Some(target)) {
+                target.parent()
+            } else {
+                from.iter().flatten().copied().find(|s| to.contains(&Some(*s)))
+            };
+
+            for state in from.iter().flatten().take_while(|s| Some(**s) != common) {
+                machine.exit(*state).await;
+            }
+            let entered = to.iter().position(|s| *s == common).unwrap_or(MAX_DEPTH);
+            for state in to[..entered].iter().rev().flatten() {
+                machine.entry(*state).await;
+            }
+
+            self.record(current.id(), next.id());
+            self.state.set(next.id());
+            current = next;
+        }
+    }
+
+    /// runs the do actions and waits for the first transition of the active states
+    async fn next_transition<M: Machine>(machine: &M, current: M::State) -> M::State {
+        let active = path(current);
+        let actions = active.map(|s| s.map(|s| machine.action(s)));
+        let transitions = active.map(|s| s.map(|s| machine.transitions(s)));
+        futures::pin_mut!(actions);
+        futures::pin_mut!(transitions);
+
+        future::poll_fn(|cx| {
+            for index in 0..MAX_DEPTH {
+                let mut action = pin_index(actions.as_mut(), index);
+                if let Some(future) = action.as_mut().as_pin_mut() {
+                    if future.poll(cx).is_ready() {
+                        action.set(None);
+                    }
+                }
+            }
+            for index in 0..MAX_DEPTH {
+                let transition = pin_index(transitions.as_mut(), index);
+                if let Some(future) = transition.as_pin_mut() {
+                    if let Poll::Ready(target) = future.poll(cx) {
+                        return Poll::Ready(target);
+                    }
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<const H: usize> Default for Hsm<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::set_system_time;
+    use core::cell::{Cell, RefCell};
+    use core::task::Context;
+    use futures::task::noop_waker_ref;
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    enum Motor {
+        Off = 1,
+        On = 2,
+        Idle = 3,
+        Running = 4,
+    }
+
+    impl StateId for Motor {
+        fn id(self) -> u16 {
+            self as u16
+        }
+
+        fn parent(self) -> Option<Motor> {
+            match self {
+                Motor::Idle | Motor::Running => Some(Motor::On),
+                _ => None,
+            }
+        }
+
+        fn initial(self) -> Option<Motor> {
+            match self {
+                Motor::On => Some(Motor::Idle),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct MotorMachine {
+        event: Cell<Option<&'static str>>,
+        log: RefCell<Vec<String>>,
+    }
+
+    impl Machine for MotorMachine {
+        type State = Motor;
+
+        async fn entry(&self, state: Motor) {
+            self.log.borrow_mut().push(format!("entry {:?}", state));
+        }
+
+        async fn exit(&self, state: Motor) {
+            self.log.borrow_mut().push(format!("exit {:?}", state));
+        }
+
+        fn transitions(&self, state: Motor) -> impl Future<Output = Motor> {
+            future::poll_fn(move |_| {
+                let target = match (state, self.event.get()) {
+                    (Motor::Off, Some("start")) => Motor::On,
+                    (Motor::Idle, Some("run")) => Motor::Running,
+                    // "run" is handled by the sub state first
+                    (Motor::On, Some("run")) | (Motor::On, Some("stop")) => Motor::Off,
+                    _ => return Poll::Pending,
+                };
+                self.event.set(None);
+                Poll::Ready(target)
+            })
+        }
+    }
+
+    #[test]
+    fn nested_states_and_history() {
+        let _time = crate::time::lock_time();
+        let machine = MotorMachine::default();
+        let hsm: Hsm<2> = Hsm::new();
+        let run = hsm.run(&machine, Motor::Off);
+        futures::pin_mut!(run);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut cycle = |time: u64, event: &'static str| {
+            set_system_time(time);
+            machine.event.set(Some(event));
+            assert!(run.as_mut().poll(&mut cx).is_pending());
+            hsm.state.get()
+        };
+
+        assert_eq!(cycle(0, ""), Motor::Off as u16);
+        assert_eq!(cycle(1_000, "start"), Motor::Idle as u16);
+        assert_eq!(cycle(2_000, "run"), Motor::Running as u16);
+        assert_eq!(cycle(3_000, "stop"), Motor::Off as u16);
+        assert_eq!(
+            *machine.log.borrow(),
+            [
+                "entry Off",
+                "exit Off",
+                "entry On",
+                "entry Idle",
+                "exit Idle",
+                "entry Running",
+                "exit Running",
+                "exit On",
+                "entry Off",
+            ]
+        );
+        assert_eq!(hsm.transitions.get(), 3);
+        assert_eq!(
+            hsm.history(0),
+            Some(Transition {
+                from: Motor::Running as u16,
+                to: Motor::Off as u16,
+                time: 3_000,
+            })
+        );
+        assert_eq!(hsm.history(1).map(|t| t.to), Some(Motor::Running as u16));
+        assert_eq!(hsm.history(2), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Interlocks that combine permissives and record the first one that tripped.
+//!
+//! ```ignore
+//! static MOTOR_INTERLOCK: Interlock<'static, 3> = Interlock::new([
+//!     Permissive::new("guard closed", &GUARD_CLOSED),
+//!     Permissive::new("pressure ok", &PRESSURE_OK),
+//!     Permissive::inverted("e-stop", &ESTOP_ACTIVE),
+//! ])
+//! .latching();
+//!
+//! loop_async! {{
+//!     MOTOR_INTERLOCK.cycle();
+//!     MOTOR_INTERLOCK.drive(&MOTOR_CMD, &MOTOR_OUT);
+//! }}
+//! ```
+
+use crate::sync::SyncCell;
+use crate::var::{Var, VarProps};
+
+/// A condition that must be met to allow a command.
+pub struct Permissive<'a> {
+    name: &'static str,
+    var: &'a (dyn VarProps<bool> + Sync),
+    active_high: bool,
+}
+
+impl<'a> Permissive<'a> {
+    /// the permissive is given while `var` is true
+    pub const fn new(name: &'static str, var: &'a (dyn VarProps<bool> + Sync)) -> Self {
+        Permissive {
+            name,
+            var,
+            active_high: true,
+        }
+    }
+
+    /// the permissive is given while `var` is false, e.g. for an e-stop or fault signal
+    pub const fn inverted(name: &'static str, var: &'a (dyn VarProps<bool> + Sync)) -> Self {
+        Permissive {
+            name,
+            var,
+            active_high: false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_given(&self) -> bool {
+        self.var.get() == self.active_high
+    }
+}
+
+/// Combines up to 32 permissives. Commands pass only while all permissives are given.
+pub struct Interlock<'a, const N: usize> {
+    permissives: [Permissive<'a>; N],
+    latching: bool,
+    tripped: SyncCell<bool>,
+    // the state that gates the commands, forcing the variables below does not bypass it
+    permitted: SyncCell<bool>,
+    missing_mask: SyncCell<u32>,
+    first: SyncCell<u16>,
+    /// true while commands pass, a read-only mirror for the host
+    pub ok: Var<bool>,
+    /// number (index + 1) of the first permissive that was missing in the last trip, for
+    /// a latching interlock in the first trip since the last reset, 0 if none
+    pub first_out: Var<u16>,
+    /// bit mask of the missing permissives
+    pub missing: Var<u32>,
+    /// number of times the interlock tripped
+    pub trips: Var<u32>,
+    /// host command: resets the first-out and a latched trip, reset to false when done
+    pub reset: Var<bool>,
+}
+
+impl<'a, const N: usize> Interlock<'a, N> {
+    pub const fn new(permissives: [Permissive<'a>; N]) -> Self {
+        assert!(N <= 32);
+        Interlock {
+            permissives,
+            latching: false,
+            tripped: SyncCell::new(false),
+            permitted: SyncCell::new(false),
+            missing_mask: SyncCell::new(0),
+            first: SyncCell::new(0),
+            ok: Var::<bool>::new(),
+            first_out: Var::<u16>::new(),
+            missing: Var::<u32>::new(),
+            trips: Var::<u32>::new(),
+            reset: Var::<bool>::new(),
+        }
+    }
+
+    /// after a trip commands stay blocked until `reset` is called, even if all
+    /// permissives are given again
+    pub const fn latching(mut self) -> Self {
+        self.latching = true;
+        self
+    }
+
+    pub fn permissive(&self, index: usize) -> Option<&Permissive<'a>> {
+        self.permissives.get(index)
+    }
+
+    /// returns the permissive that tripped first, see `first_out`
+    pub fn first_out_permissive(&self) -> Option<&Permissive<'a>> {
+        match self.first.get() {
+            0 => None,
+            number => self.permissives.get(number as usize - 1),
+        }
+    }
+
+    /// Evaluates the permissives, call it once per cycle before the commands are gated.
+    /// If several permissives drop in the same cycle the first one in the list is
+    /// recorded as first-out.
+    pub fn cycle(&self) {
+        if self.reset.get() {
+            self.reset.set(false);
+            self.reset();
+        }
+        let missing = self
+            .permissives
+            .iter()
+            .enumerate()
+            .filter(|(_, permissive)| !permissive.is_given())
+            .fold(0u32, |mask, (i, _)| mask | 1 << i);
+        let first = missing.trailing_zeros() as u16 + 1;
+        if missing != 0 && self.missing_mask.get() == 0 {
+            self.trips.set(self.trips.get().wrapping_add(1));
+            // a latching interlock keeps the first-out of the trip that latched it
+            if !self.latching {
+                self.first.set(first);
+            }
+        }
+        if missing != 0 && self.first.get() == 0 {
+            self.first.set(first);
+        }
+        if missing != 0 {
+            self.tripped.set(true);
+        }
+        self.missing_mask.set(missing);
+        self.permitted.set(missing == 0 && !(self.latching && self.tripped.get()));
+        self.mirror();
+    }
+
+    fn mirror(&self) {
+        self.ok.set(self.permitted.get());
+        self.first_out.set(self.first.get());
+        self.missing.set(self.missing_mask.get());
+    }
+
+    /// Clears the first-out and a latched trip. While permissives are still missing the
+    /// next `cycle` records the first missing one again.
+    pub fn reset(&self) {
+        self.first.set(0);
+        self.tripped.set(false);
+        self.permitted.set(self.missing_mask.get() == 0);
+        self.mirror();
+    }
+
+    /// returns true while commands pass
+    pub fn is_ok(&self) -> bool {
+        self.permitted.get()
+    }
+
+    /// returns `command` if the interlock is ok, false otherwise
+    pub fn gate(&self, command: bool) -> bool {
+        command && self.permitted.get()
+    }
+
+    /// sets `output` to `command` while the interlock is ok, false otherwise
+    pub fn drive(&self, command: &dyn VarProps<bool>, output: &dyn VarProps<bool>) {
+        output.set(self.gate(command.get()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_out_of_every_trip() {
+        let guard = Var::<bool>::new();
+        let pressure = Var::<bool>::new();
+        let estop = Var::<bool>::new();
+        let interlock = Interlock::new([
+            Permissive::new("guard closed", &guard),
+            Permissive::new("pressure ok", &pressure),
+            Permissive::inverted("e-stop", &estop),
+        ]);
+        guard.set(true);
+        pressure.set(true);
+        interlock.cycle();
+        assert!(interlock.ok.get());
+        assert_eq!(interlock.first_out.get(), 0);
+
+        pressure.set(false);
+        interlock.cycle();
+        estop.set(true);
+        interlock.cycle();
+        assert!(!interlock.ok.get());
+        assert_eq!(interlock.first_out_permissive().unwrap().name(), "pressure ok");
+        assert_eq!(interlock.missing.get(), 0b110);
+
+        pressure.set(true);
+        estop.set(false);
+        interlock.cycle();
+        assert!(interlock.ok.get());
+        guard.set(false);
+        interlock.cycle();
+        assert_eq!(interlock.first_out_permissive().unwrap().name(), "guard closed");
+        assert_eq!(interlock.trips.get(), 2);
+    }
+
+    #[test]
+    fn latching_keeps_first_out_until_reset() {
+        let guard = Var::<bool>::new();
+        let pressure = Var::<bool>::new();
+        let interlock = Interlock::new([
+            Permissive::new("guard closed", &guard),
+            Permissive::new("pressure ok", &pressure),
+        ])
+        .latching();
+        guard.set(true);
+        pressure.set(true);
+        interlock.cycle();
+        pressure.set(false);
+        interlock.cycle();
+        pressure.set(true);
+        interlock.cycle();
+        assert!(!interlock.ok.get());
+        assert!(!interlock.gate(true));
+
+        guard.set(false);
+        interlock.cycle();
+        guard.set(true);
+        interlock.cycle();
+        assert_eq!(interlock.first_out.get(), 2);
+
+        interlock.reset.set(true);
+        interlock.cycle();
+        assert!(interlock.ok.get());
+        assert!(!interlock.reset.get());
+        assert_eq!(interlock.first_out.get(), 0);
+    }
+
+    #[test]
+    fn forced_mirror_does_not_bypass() {
+        use crate::var::MemVar;
+        let guard = Var::<bool>::new();
+        let interlock = Interlock::new([Permissive::new("guard closed", &guard)]);
+        interlock.cycle();
+        unsafe {
+            interlock.ok.from_buffer([1u8].as_ptr(), 3);
+            interlock.ok.set_forced(1);
+            interlock.missing.from_buffer([0u8; 4].as_ptr(), 3);
+            interlock.missing.set_forced(1);
+        }
+        assert!(interlock.ok.get());
+        interlock.cycle();
+        assert!(!interlock.is_ok() && !interlock.gate(true));
+
+        // a forced missing mask does not hide the next trip
+        guard.set(true);
+        interlock.cycle();
+        guard.set(false);
+        interlock.cycle();
+        assert_eq!(interlock.trips.get(), 2);
+    }
+}
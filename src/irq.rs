@@ -0,0 +1,427 @@
+//! Sharing data between interrupt handlers and tasks.
+//!
+//! Everything in this module is protected by a critical section that is provided by the
+//! target runtime through a `CriticalSectionImpl` set with `set_critical_section`,
+//! e.g. by disabling interrupts on a single core MCU. Host builds with the `std` feature
+//! and unit tests run single threaded and default to `NoCriticalSection`.
+
+use crate::poll::poll_called;
+use crate::sync::SyncCell;
+use crate::var::{MemVar, NumVar, ScalarVar, Var, VarChange, VarProps, SubscribeMode};
+use core::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
+use futures::future;
+
+/// Enters and leaves critical sections, provided by the target runtime.
+pub trait CriticalSectionImpl: Sync {
+    /// Enters a critical section and returns the state to restore,
+    /// e.g. disables interrupts and returns whether they were enabled.
+    fn acquire(&self) -> u32;
+    /// Leaves a critical section entered by `acquire`.
+    fn release(&self, state: u32);
+}
+
+/// Does nothing, only valid if no interrupt handler touches shared data,
+/// e.g. on the host.
+pub struct NoCriticalSection;
+
+impl CriticalSectionImpl for NoCriticalSection {
+    fn acquire(&self) -> u32 {
+        0
+    }
+
+    fn release(&self, _state: u32) {}
+}
+
+static CRITICAL_SECTION: SyncCell<Option<&'static dyn CriticalSectionImpl>> = SyncCell::new(None);
+
+/// Sets the critical section implementation, must be called during startup before
+/// interrupts that use this module are enabled.
+pub fn set_critical_section(implementation: &'static dyn CriticalSectionImpl) {
+    CRITICAL_SECTION.set(Some(implementation));
+}
+
+#[cfg(not(any(test, feature = "std")))]
+fn critical_section() -> &'static dyn CriticalSectionImpl {
+    CRITICAL_SECTION
+        .get()
+        .expect("no critical section set, call irq::set_critical_section during startup")
+}
+
+#[cfg(any(test, feature = "std"))]
+fn critical_section() -> &'static dyn CriticalSectionImpl {
+    CRITICAL_SECTION.get().unwrap_or(&NoCriticalSection)
+}
+
+/// Token that proves that the code runs inside a critical section.
+pub struct CriticalSection<'cs> {
+    _private: PhantomData<&'cs ()>,
+}
+
+/// Runs `f` inside a critical section, critical sections can be nested.
+pub fn with<R>(f: impl FnOnce(&CriticalSection) -> R) -> R {
+    let critical_section = critical_section();
+    let state = critical_section.acquire();
+    let result = f(&CriticalSection {
+        _private: PhantomData,
+    });
+    critical_section.release(state);
+    result
+}
+
+/// A `Cell` that can be shared between interrupt handlers and tasks.
+pub struct IrqCell<T> {
+    value: Cell<T>,
+}
+
+// SAFETY: the value is only accessed inside critical sections
+unsafe impl<T: Send> Sync for IrqCell<T> {}
+
+impl<T> IrqCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: Cell::new(value),
+        }
+    }
+
+    /// gives access to the cell for several operations inside one critical section
+    pub fn borrow<'cs>(&'cs self, _cs: &'cs CriticalSection) -> &'cs Cell<T> {
+        &self.value
+    }
+
+    pub fn set(&self, value: T) {
+        with(|cs| self.borrow(cs).set(value))
+    }
+
+    pub fn replace(&self, value: T) -> T {
+        with(|cs| self.borrow(cs).replace(value))
+    }
+}
+
+impl<T: Copy> IrqCell<T> {
+    pub fn get(&self) -> T {
+        with(|cs| self.borrow(cs).get())
+    }
+
+    /// updates the value in a single critical section and returns the new value
+    pub fn update(&self, f: impl FnOnce(T) -> T) -> T {
+        with(|cs| {
+            let cell = self.borrow(cs);
+            let value = f(cell.get());
+            cell.set(value);
+            value
+        })
+    }
+}
+
+/// An event flag that is set in an interrupt handler and taken by a task.
+pub struct IrqLatch {
+    set: IrqCell<bool>,
+}
+
+impl IrqLatch {
+    pub const fn new() -> Self {
+        Self {
+            set: IrqCell::new(false),
+        }
+    }
+
+    /// sets the latch, can be called from interrupt handlers
+    pub fn set(&self) {
+        self.set.set(true);
+    }
+
+    /// returns true if the latch was set and clears it
+    pub fn take(&self) -> bool {
+        self.set.replace(false)
+    }
+
+    /// waits until the latch is set and clears it
+    pub async fn wait(&self) {
+        future::poll_fn(|_| {
+            poll_called();
+            if self.take() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl Default for IrqLatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A lock-free single-producer, single-consumer queue with capacity `N - 1`,
+/// e.g. to hand over captured values from an interrupt handler to a task.
+///
+/// Only uses atomic loads and stores, so it also works on cores without
+/// compare-and-swap instructions.
+pub struct SpscQueue<T, const N: usize> {
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// SAFETY: producer and consumer work on disjoint slots, see `enqueue` and `dequeue`
+unsafe impl<T: Send, const N: usize> Sync for SpscQueue<T, N> {}
+
+impl<T, const N: usize> SpscQueue<T, N> {
+    /// Creates an empty queue, `N` must be at least 2 since one slot always stays free.
+    pub const fn new() -> Self {
+        assert!(N > 1, "SpscQueue needs N > 1");
+        Self {
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        // SAFETY: indices are always smaller than N
+        unsafe { (self.buffer.get() as *mut T).add(index) }
+    }
+
+    /// returns true if no value is queued
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// Queues a value, returns it back if the queue is full.
+    ///
+    /// # Safety
+    /// Must only be called from one context (the producer), e.g. from a single
+    /// interrupt handler.
+    pub unsafe fn enqueue(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        self.slot(tail).write(value);
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Takes the oldest value.
+    ///
+    /// # Safety
+    /// Must only be called from one context (the consumer), e.g. from a single task.
+    pub unsafe fn dequeue(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = self.slot(head).read();
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(value)
+    }
+}
+
+impl<T, const N: usize> Default for SpscQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SpscQueue<T, N> {
+    fn drop(&mut self) {
+        // SAFETY: `&mut self` guarantees there is no other producer or consumer
+        while unsafe { self.dequeue() }.is_some() {}
+    }
+}
+
+/// A `Var` that may be accessed from interrupt handlers.
+///
+/// Every access to the inner variable runs inside a critical section, so `set` can be
+/// called from an interrupt handler and marks the variable dirty safely.
+///
+/// ```ignore
+/// static ENCODER: IrqVar<i32> = IrqVar::new(Var::new());
+///
+/// fn encoder_isr() {
+///     ENCODER.inc(1);
+/// }
+/// ```
+pub struct IrqVar<T: Default> {
+    var: Var<T>,
+}
+
+// SAFETY: the variable is only accessed inside critical sections
+unsafe impl<T: Default + Send> Sync for IrqVar<T> {}
+
+impl<T: Default> IrqVar<T> {
+    pub const fn new(var: Var<T>) -> Self {
+        Self { var }
+    }
+}
+
+impl<T: Default> VarProps<T> for IrqVar<T>
+where
+    Var<T>: VarProps<T>,
+{
+    fn get(&self) -> T {
+        with(|_| self.var.get())
+    }
+
+    fn set(&self, value: T) {
+        with(|_| self.var.set(value))
+    }
+
+    fn subscribe(&self, value: SubscribeMode) {
+        with(|_| self.var.subscribe(value))
+    }
+}
+
+impl<T: Default> NumVar<T> for IrqVar<T>
+where
+    Var<T>: NumVar<T>,
+{
+    fn inc(&self, add: T) {
+        with(|_| self.var.inc(add))
+    }
+
+    fn add(&self, add: T) -> bool {
+        with(|_| self.var.add(add))
+    }
+
+    fn sub(&self, substract: T) -> bool {
+        with(|_| self.var.sub(substract))
+    }
+
+    fn delta(&self, delta: T) {
+        with(|_| self.var.delta(delta))
+    }
+}
+
+impl<T: Default> VarChange for IrqVar<T>
+where
+    Var<T>: VarChange<VarType = T>,
+{
+    type VarType = T;
+
+    fn get_value(&self) -> T {
+        with(|_| self.var.get_value())
+    }
+
+    fn is_posedge(&self, value: T) -> bool {
+        with(|_| self.var.is_posedge(value))
+    }
+
+    fn is_negedge(&self, value: T) -> bool {
+        with(|_| self.var.is_negedge(value))
+    }
+
+    fn is_unread(&self) -> bool {
+        with(|_| self.var.is_unread())
+    }
+}
+
+impl<T: Default + Send> ScalarVar for IrqVar<T>
+where
+    Var<T>: ScalarVar,
+{
+    fn get_f64(&self) -> f64 {
+        with(|_| self.var.get_f64())
+    }
+
+    fn set_f64(&self, value: f64) {
+        with(|_| self.var.set_f64(value))
+    }
+}
+
+impl<T: Default + Send> MemVar for IrqVar<T>
+where
+    Var<T>: MemVar,
+{
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        with(|_| self.var.to_buffer(buffer, subvalue))
+    }
+
+    unsafe fn from_buffer(&self, buffer: *const u8, subvalue: u8) -> u8 {
+        with(|_| self.var.from_buffer(buffer, subvalue))
+    }
+
+    unsafe fn is_dirty(&self) -> bool {
+        with(|_| self.var.is_dirty())
+    }
+
+    unsafe fn clear_dirty(&self) {
+        with(|_| self.var.clear_dirty())
+    }
+
+    unsafe fn get_forced(&self) -> u8 {
+        with(|_| self.var.get_forced())
+    }
+
+    unsafe fn set_forced(&self, value: u8) {
+        with(|_| self.var.set_forced(value))
+    }
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        with(|_| self.var.get_subscribed())
+    }
+
+    unsafe fn set_subscribed(&self, value: u8) {
+        with(|_| self.var.set_subscribed(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingCriticalSection {
+        depth: SyncCell<u32>,
+    }
+
+    impl CriticalSectionImpl for CountingCriticalSection {
+        fn acquire(&self) -> u32 {
+            self.depth.replace(self.depth.get() + 1)
+        }
+
+        fn release(&self, state: u32) {
+            self.depth.set(state);
+        }
+    }
+
+    static COUNTING: CountingCriticalSection = CountingCriticalSection {
+        depth: SyncCell::new(0),
+    };
+
+    #[test]
+    fn nested_critical_sections() {
+        set_critical_section(&COUNTING);
+        let cell = IrqCell::new(1);
+        let depth = with(|cs| {
+            cell.borrow(cs).set(2);
+            with(|_| COUNTING.depth.get())
+        });
+        assert_eq!((depth, COUNTING.depth.get()), (2, 0));
+        assert_eq!(cell.update(|value| value * 3), 6);
+    }
+
+    #[test]
+    fn spsc_queue_keeps_one_slot_free() {
+        let queue = SpscQueue::<u8, 3>::new();
+        unsafe {
+            assert_eq!(queue.enqueue(1), Ok(()));
+            assert_eq!(queue.enqueue(2), Ok(()));
+            assert_eq!(queue.enqueue(3), Err(3));
+            assert_eq!(queue.dequeue(), Some(1));
+            assert_eq!(queue.enqueue(3), Ok(()));
+            assert_eq!(queue.dequeue(), Some(2));
+            assert_eq!(queue.dequeue(), Some(3));
+            assert_eq!(queue.dequeue(), None);
+        }
+    }
+}
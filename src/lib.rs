@@ -1,15 +1,49 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 mod memvar_macro;
 
+// the exported macros refer to the crate by name
+#[cfg(test)]
+extern crate self as pilot_sys;
+
 pub mod time;
 pub mod sync;
+pub mod irq;
 pub mod var;
 pub mod async_util;
+pub mod timeout;
+pub mod sfc;
+pub mod hsm;
+pub mod packml;
+pub mod cancel;
+pub mod cycle;
+pub mod watchdog;
+pub mod schedule;
+pub mod pid;
+pub mod filter;
+pub mod alarm;
+pub mod interlock;
+pub mod recipe;
+pub mod trend;
+pub mod trace;
+pub mod computed;
+pub mod group;
+#[cfg(feature = "std")]
+pub mod vcd;
+pub mod fault;
 
 #[macro_use]
 pub mod print;
 
+#[macro_use]
+pub mod log;
+
+#[macro_use]
+pub mod binlog;
+#[cfg(feature = "std")]
+pub mod binlog_decode;
+pub mod ringlog;
+
 #[macro_use]
 pub mod poll;
 
@@ -0,0 +1,343 @@
+//! Leveled logging with compile-time and runtime filters.
+//!
+//! ```ignore
+//! info!("batch {} started", BATCH.get());
+//! warn!("pressure low: {}", PRESSURE.get());
+//!
+//! // silence the chatty communication module, log everything else from info up
+//! log::set_max_level(LevelFilter::Info);
+//! log::set_module_filters(&[("my_plc::comm", LevelFilter::Error)]);
+//! ```
+//!
+//! Levels above the one selected with the `max_level_*` features are compiled out.
+//! Modules are filtered at compile time with the `PILOT_LOG_MODULES` environment
+//! variable, read when pilot_sys is built, e.g. in `.cargo/config.toml`:
+//!
+//! ```toml
+//! [env]
+//! PILOT_LOG_MODULES = "my_plc::comm=error,my_plc::comm::modbus=debug"
+//! ```
+//!
+//! Entries are `path=level` pairs separated by commas without spaces, the level is
+//! one of `off`, `error`, `warn`, `info`, `debug` and `trace`. Records of `error!` to
+//! `trace!` that this table filters are eliminated at compile time. The runtime module
+//! filters set with `set_module_filters` can only restrict the output further: a record
+//! they filter costs the filter lookup but its arguments are never formatted.
+//!
+//! The macros are exported at the crate root like the macros of the `log` crate. Do not
+//! glob import both crates, call them as `pilot_sys::info!` or through the re-exports in
+//! this module, e.g. `use pilot_sys::log::{info, warn};`.
+//! All output, including `print!` and `println!`, goes through the `LogSink` set with
+//! `set_sink`, by default the `_putchar` based `print::PutcharSink`, or
+//! `print::StdoutSink` with the `std` feature and in unit tests.
+
+use crate::sync::SyncCell;
+use core::fmt;
+
+/// Severity of a log record.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Maximum level that is logged, `Off` disables logging.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[repr(u8)]
+pub enum LevelFilter {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// Maximum level compiled into the program, selected with the `max_level_*` features.
+pub const STATIC_MAX_LEVEL: LevelFilter = if cfg!(feature = "max_level_off") {
+    LevelFilter::Off
+} else if cfg!(feature = "max_level_error") {
+    LevelFilter::Error
+} else if cfg!(feature = "max_level_warn") {
+    LevelFilter::Warn
+} else if cfg!(feature = "max_level_info") {
+    LevelFilter::Info
+} else if cfg!(feature = "max_level_debug") {
+    LevelFilter::Debug
+} else {
+    LevelFilter::Trace
+};
+
+/// Compile-time module filters from the `PILOT_LOG_MODULES` environment variable.
+pub const STATIC_MODULE_FILTERS: &str = match option_env!("PILOT_LOG_MODULES") {
+    Some(filters) => filters,
+    None => "",
+};
+
+const fn bytes_eq(bytes: &[u8], start: usize, end: usize, other: &[u8]) -> bool {
+    if end - start != other.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < other.len() {
+        if bytes[start + i] != other[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn parse_level(bytes: &[u8], start: usize, end: usize) -> LevelFilter {
+    if bytes_eq(bytes, start, end, b"off") {
+        LevelFilter::Off
+    } else if bytes_eq(bytes, start, end, b"error") {
+        LevelFilter::Error
+    } else if bytes_eq(bytes, start, end, b"warn") {
+        LevelFilter::Warn
+    } else if bytes_eq(bytes, start, end, b"info") {
+        LevelFilter::Info
+    } else if bytes_eq(bytes, start, end, b"debug") {
+        LevelFilter::Debug
+    } else if bytes_eq(bytes, start, end, b"trace") {
+        LevelFilter::Trace
+    } else {
+        panic!("PILOT_LOG_MODULES: unknown level")
+    }
+}
+
+/// Returns the level of the longest entry of `filters` that matches `module`, the
+/// entries use the format of `PILOT_LOG_MODULES`.
+pub const fn module_level(filters: &str, module: &str) -> Option<LevelFilter> {
+    let filters = filters.as_bytes();
+    let module = module.as_bytes();
+    let mut best: Option<(usize, LevelFilter)> = None;
+    let mut start = 0;
+    while start < filters.len() {
+        let mut end = start;
+        while end < filters.len() && filters[end] != b',' {
+            end += 1;
+        }
+        let mut separator = start;
+        while separator < end && filters[separator] != b'=' {
+            separator += 1;
+        }
+        if separator == end {
+            panic!("PILOT_LOG_MODULES: expected `path=level`");
+        }
+        let level = parse_level(filters, separator + 1, end);
+        let (_, path) = filters.split_at(start);
+        let (path, _) = path.split_at(separator - start);
+        let len = path.len();
+        let matches = len <= module.len()
+            && bytes_eq(module, 0, len, path)
+            && (len == module.len() || (len + 1 < module.len() && module[len] == b':' && module[len + 1] == b':'));
+        let longer = match best {
+            Some((best_len, _)) => len > best_len,
+            None => true,
+        };
+        if matches && longer {
+            best = Some((len, level));
+        }
+        start = end + 1;
+    }
+    match best {
+        Some((_, level)) => Some(level),
+        None => None,
+    }
+}
+
+/// Returns true if `level` passes the `max_level_*` features and the compile-time module
+/// filters for `module`, the log macros evaluate it in a const block.
+pub const fn static_enabled(level: Level, module: &str) -> bool {
+    if level as u8 > STATIC_MAX_LEVEL as u8 {
+        return false;
+    }
+    match module_level(STATIC_MODULE_FILTERS, module) {
+        Some(filter) => level as u8 <= filter as u8,
+        None => true,
+    }
+}
+
+/// A log record passed to the `LogSink`.
+pub struct Record<'a> {
+    /// `None` for output of `print!` and `println!`
+    pub level: Option<Level>,
+    /// module path of the call site, empty for `print!` and `println!`
+    pub module: &'a str,
+    pub args: fmt::Arguments<'a>,
+    /// true if the record ends the line
+    pub newline: bool,
+}
+
+/// Destination of log output, e.g. a UART, RTT, a RAM buffer or a test capture.
+pub trait LogSink: Sync {
+    fn log(&self, record: &Record);
+
+    /// writes raw bytes, e.g. frames of `binlog!`, sinks without binary support drop them
+    fn write_bytes(&self, _bytes: &[u8]) {}
+}
+
+static SINK: SyncCell<Option<&'static dyn LogSink>> = SyncCell::new(None);
+static MAX_LEVEL: SyncCell<LevelFilter> = SyncCell::new(LevelFilter::Trace);
+static MODULE_FILTERS: SyncCell<&'static [(&'static str, LevelFilter)]> = SyncCell::new(&[]);
+
+/// sets the sink all log output is written to
+pub fn set_sink(sink: &'static dyn LogSink) {
+    SINK.set(Some(sink));
+}
+
+/// returns the current sink
+#[cfg(not(any(test, feature = "std")))]
+pub fn sink() -> &'static dyn LogSink {
+    SINK.get().unwrap_or(&crate::print::PutcharSink)
+}
+
+/// returns the current sink
+#[cfg(any(test, feature = "std"))]
+pub fn sink() -> &'static dyn LogSink {
+    SINK.get().unwrap_or(&crate::print::StdoutSink)
+}
+
+/// sets the maximum level for modules without a module filter
+pub fn set_max_level(level: LevelFilter) {
+    MAX_LEVEL.set(level);
+}
+
+pub fn max_level() -> LevelFilter {
+    MAX_LEVEL.get()
+}
+
+/// Sets per module filters as `(module path, level)` pairs. A filter applies to the
+/// module and its sub modules, the longest matching module path wins.
+pub fn set_module_filters(filters: &'static [(&'static str, LevelFilter)]) {
+    MODULE_FILTERS.set(filters);
+}
+
+fn module_matches(module: &str, filter: &str) -> bool {
+    match module.strip_prefix(filter) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// returns true if a record with `level` from `module` is logged
+#[inline(always)]
+pub fn enabled(level: Level, module: &str) -> bool {
+    if !static_enabled(level, module) {
+        return false;
+    }
+    let filter = MODULE_FILTERS
+        .get()
+        .iter()
+        .filter(|(path, _)| module_matches(module, path))
+        .max_by_key(|(path, _)| path.len())
+        .map_or(MAX_LEVEL.get(), |(_, filter)| *filter);
+    level as u8 <= filter as u8
+}
+
+/// Writes a record to the sink, used by the log macros.
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    sink().log(&Record {
+        level: Some(level),
+        module,
+        args,
+        newline: true,
+    });
+}
+
+/// Logs a message with the given level if it passes the filters.
+#[macro_export]
+macro_rules! log {
+    // a constant level, records filtered at compile time are eliminated
+    (const $level:path, $($arg:tt)+) => ({
+        if const { $crate::log::static_enabled($level, module_path!()) }
+            && $crate::log::enabled($level, module_path!())
+        {
+            $crate::log::log($level, module_path!(), format_args!($($arg)+));
+        }
+    });
+    ($level:expr, $($arg:tt)+) => ({
+        let level = $level;
+        if $crate::log::enabled(level, module_path!()) {
+            $crate::log::log(level, module_path!(), format_args!($($arg)+));
+        }
+    });
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!(const $crate::log::Level::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!(const $crate::log::Level::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!(const $crate::log::Level::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log!(const $crate::log::Level::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!(const $crate::log::Level::Trace, $($arg)+));
+}
+
+pub use crate::{debug, error, info, log, trace, warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_module_filters() {
+        const FILTERS: &str = "plc::comm=error,plc::comm::modbus=debug,plc=off";
+        const COMM: Option<LevelFilter> = module_level(FILTERS, "plc::comm::tcp");
+        assert_eq!(COMM, Some(LevelFilter::Error));
+        assert_eq!(module_level(FILTERS, "plc::comm::modbus"), Some(LevelFilter::Debug));
+        assert_eq!(module_level(FILTERS, "plc::communication"), Some(LevelFilter::Off));
+        assert_eq!(module_level(FILTERS, "plant"), None);
+        assert_eq!(module_level("", "plc"), None);
+        // without `PILOT_LOG_MODULES` only the features filter
+        assert_eq!(static_enabled(Level::Error, module_path!()), Level::Error as u8 <= STATIC_MAX_LEVEL as u8);
+    }
+
+    #[test]
+    fn module_filters() {
+        // `--all-features` also compiles out all levels
+        let compiled = |level: Level| level as u8 <= STATIC_MAX_LEVEL as u8;
+        set_module_filters(&[("plc::comm", LevelFilter::Error), ("plc::comm::modbus", LevelFilter::Debug)]);
+        assert_eq!(enabled(Level::Info, "plc::main"), compiled(Level::Info));
+        assert!(!enabled(Level::Info, "plc::comm"));
+        assert!(!enabled(Level::Warn, "plc::comm::tcp"));
+        assert_eq!(enabled(Level::Debug, "plc::comm::modbus"), compiled(Level::Debug));
+        assert_eq!(enabled(Level::Info, "plc::communication"), compiled(Level::Info));
+        set_module_filters(&[]);
+        // the default sink in tests is stdout, not `_putchar`
+        info!("module filters checked");
+        println!();
+    }
+}
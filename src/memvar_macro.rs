@@ -36,6 +36,7 @@ macro_rules! var_impl {
                     3 => self.forced_value.set(*(buffer as *const $t)),
                     _ => self.set(*(buffer as *const $t)),
                 };
+                self.changed();
                 core::mem::size_of::<$t>() as u8
             }
 
@@ -60,6 +61,7 @@ macro_rules! var_impl {
                 } else {
                     self.forced.set(false);
                 }
+                self.changed();
             }
 
             unsafe fn get_subscribed(&self) -> u8 {
@@ -91,6 +93,7 @@ macro_rules! var_impl {
             fn set(&self, value: $t) {
                 if (value != self.value.get()) {
                     self.value.set(value);
+                    self.changed();
 
                     if self.subscribed.get() != SubscribeMode::Off {
                         let stored = self.changed_value.get();
@@ -121,6 +124,16 @@ macro_rules! var_impl {
             }
         }
 
+        impl ScalarVar for Var<$t> {
+            fn get_f64(&self) -> f64 {
+                self.get() as f64
+            }
+
+            fn set_f64(&self, value: f64) {
+                self.set(value as $t);
+            }
+        }
+
         impl VarChange for Var<$t> {
             type VarType = $t;
 
@@ -149,12 +162,14 @@ macro_rules! var_impl {
                         .value
                         .set(<$t>::MIN + (add - (<$t>::MAX - self.value.get()))),
                 }
+                self.changed();
             }
 
             fn add(&self, add: $t) -> bool {
                 match self.value.get().checked_add(add) {
                     Some(t) => {
                         self.value.set(t);
+                        self.changed();
                         true
                     }
                     None => false,
@@ -165,6 +180,7 @@ macro_rules! var_impl {
                 match self.value.get().checked_sub(substract) {
                     Some(t) => {
                         self.value.set(t);
+                        self.changed();
                         true
                     }
                     None => false,
@@ -177,3 +193,200 @@ macro_rules! var_impl {
         }
     };
 }
+
+//Floating point type implementation Macro
+#[macro_export]
+macro_rules! var_float_impl {
+    ($t:ty) => {
+        impl Var<$t> {
+            pub const fn new() -> Var<$t> {
+                Var {
+                    value: SyncCell::new(0.0),
+                    forced_value: SyncCell::new(0.0),
+                    changed_value: SyncCell::new(0.0),
+                    forced: SyncCell::new(false),
+                    min_delta: SyncCell::new(0.0),
+                    subscribed: SyncCell::new(SubscribeMode::Off),
+                    dirty: SyncCell::new(false),
+                }
+            }
+        }
+
+        impl Default for Var<$t> {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl MemVar for Var<$t> {
+            unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+                *(buffer as *mut $t) = match subvalue {
+                    0 => self.get(),
+                    1 => self.value.get(),
+                    2 => self.changed_value.get(),
+                    3 => self.forced_value.get(),
+                    _ => self.get(),
+                };
+                core::mem::size_of::<$t>() as u8
+            }
+
+            unsafe fn from_buffer(&self, buffer: *const u8, subvalue: u8) -> u8 {
+                match subvalue {
+                    0 => self.set(*(buffer as *const $t)),
+                    1 => self.value.set(*(buffer as *const $t)),
+                    2 => self.changed_value.set(*(buffer as *const $t)),
+                    3 => self.forced_value.set(*(buffer as *const $t)),
+                    _ => self.set(*(buffer as *const $t)),
+                };
+                self.changed();
+                core::mem::size_of::<$t>() as u8
+            }
+
+            unsafe fn is_dirty(&self) -> bool {
+                self.dirty.get()
+            }
+
+            unsafe fn clear_dirty(&self) {
+                self.dirty.set(false);
+            }
+
+            unsafe fn get_forced(&self) -> u8 {
+                match self.forced.get() {
+                    true => 1,
+                    false => 0,
+                }
+            }
+
+            unsafe fn set_forced(&self, value: u8) {
+                if value > 0 {
+                    self.forced.set(true);
+                } else {
+                    self.forced.set(false);
+                }
+                self.changed();
+            }
+
+            unsafe fn get_subscribed(&self) -> u8 {
+                match self.subscribed.get() {
+                    SubscribeMode::Off => 0,
+                    SubscribeMode::Sticky => 1,
+                    SubscribeMode::Current => 2,
+                }
+            }
+
+            unsafe fn set_subscribed(&self, value: u8) {
+                match value {
+                    0 => self.subscribed.set(SubscribeMode::Off),
+                    1 => self.subscribed.set(SubscribeMode::Sticky),
+                    2 => self.subscribed.set(SubscribeMode::Current),
+                    _ => (),
+                }
+            }
+        }
+
+        impl VarProps<$t> for Var<$t> {
+            fn get(&self) -> $t {
+                match self.forced.get() {
+                    true => self.forced_value.get(),
+                    false => self.value.get(),
+                }
+            }
+
+            fn set(&self, value: $t) {
+                if value != self.value.get() {
+                    self.value.set(value);
+                    self.changed();
+
+                    if self.subscribed.get() != SubscribeMode::Off {
+                        let stored = self.changed_value.get();
+                        let delta = self.min_delta.get();
+                        // a delta of 0 reports every change
+                        if value - stored >= delta || stored - value >= delta {
+                            match self.subscribed.get() {
+                                SubscribeMode::Sticky if !self.dirty.get() && stored != value => {
+                                    self.changed_value.set(value);
+                                    self.dirty.set(true);
+                                }
+                                SubscribeMode::Current if stored != value => {
+                                    self.changed_value.set(value);
+                                    self.dirty.set(true);
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+            }
+
+            fn subscribe(&self, value: SubscribeMode) {
+                self.subscribed.set(value);
+            }
+        }
+
+        impl ScalarVar for Var<$t> {
+            fn get_f64(&self) -> f64 {
+                self.get() as f64
+            }
+
+            fn set_f64(&self, value: f64) {
+                self.set(value as $t);
+            }
+        }
+
+        impl VarChange for Var<$t> {
+            type VarType = $t;
+
+            fn get_value(&self) -> $t {
+                self.value.get()
+            }
+
+            fn is_posedge(&self, snapshot: $t) -> bool {
+                let value = self.value.get();
+                value > snapshot && value - snapshot >= self.min_delta.get()
+            }
+
+            fn is_negedge(&self, snapshot: $t) -> bool {
+                let value = self.value.get();
+                value < snapshot && snapshot - value >= self.min_delta.get()
+            }
+
+            fn is_unread(&self) -> bool {
+                self.dirty.get()
+            }
+        }
+
+        impl NumVar<$t> for Var<$t> {
+            /// floating point values do not wrap around, the value saturates at infinity
+            fn inc(&self, add: $t) {
+                self.value.set(self.value.get() + add);
+                self.changed();
+            }
+
+            fn add(&self, add: $t) -> bool {
+                let value = self.value.get() + add;
+                if value.is_finite() {
+                    self.value.set(value);
+                    self.changed();
+                    true
+                } else {
+                    false
+                }
+            }
+
+            fn sub(&self, substract: $t) -> bool {
+                let value = self.value.get() - substract;
+                if value.is_finite() {
+                    self.value.set(value);
+                    self.changed();
+                    true
+                } else {
+                    false
+                }
+            }
+
+            fn delta(&self, delta: $t) {
+                self.min_delta.set(delta);
+            }
+        }
+    };
+}
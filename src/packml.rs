@@ -0,0 +1,167 @@
+//! PackML (ISA-TR88.00.02) base state model as a template for `hsm::Machine`.
+//!
+//! ```ignore
+//! static COMMAND: Var<u8> = Var::new();
+//! static MACHINE: Hsm<32> = Hsm::new();
+//!
+//! struct Filler;
+//!
+//! impl Machine for Filler {
+//!     type State = PackMl;
+//!
+//!     fn transitions(&self, state: PackMl) -> impl Future<Output = PackMl> {
+//!         packml::transitions(state, &COMMAND, async move {
+//!             // the acting states finish with state complete (SC)
+//!             match state {
+//!                 PackMl::Starting => wait(Duration::from_secs(2)).await,
+//!                 PackMl::Execute => BATCH_DONE.pos().await,
+//!                 _ => (),
+//!             }
+//!         })
+//!     }
+//! }
+//!
+//! MACHINE.run(&Filler, PackMl::Stopped).await;
+//! ```
+
+use crate::async_util::either;
+use crate::hsm::StateId;
+use crate::poll::poll_called;
+use crate::var::{Var, VarProps};
+use core::future::Future;
+use core::task::Poll;
+use futures::future;
+
+/// PackML states, the discriminants are the standard state numbers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u16)]
+pub enum PackMl {
+    Clearing = 1,
+    Stopped = 2,
+    Starting = 3,
+    Idle = 4,
+    Suspended = 5,
+    Execute = 6,
+    Stopping = 7,
+    Aborting = 8,
+    Aborted = 9,
+    Holding = 10,
+    Held = 11,
+    Unholding = 12,
+    Suspending = 13,
+    Unsuspending = 14,
+    Resetting = 15,
+    Completing = 16,
+    Complete = 17,
+}
+
+/// PackML commands, the discriminants are the standard command numbers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum PackMlCommand {
+    Reset = 1,
+    Start = 2,
+    Stop = 3,
+    Hold = 4,
+    Unhold = 5,
+    Suspend = 6,
+    Unsuspend = 7,
+    Abort = 8,
+    Clear = 9,
+}
+
+impl PackMlCommand {
+    /// converts a standard command number, returns `None` for unknown numbers
+    pub fn from_u8(value: u8) -> Option<PackMlCommand> {
+        match value {
+            1 => Some(PackMlCommand::Reset),
+            2 => Some(PackMlCommand::Start),
+            3 => Some(PackMlCommand::Stop),
+            4 => Some(PackMlCommand::Hold),
+            5 => Some(PackMlCommand::Unhold),
+            6 => Some(PackMlCommand::Suspend),
+            7 => Some(PackMlCommand::Unsuspend),
+            8 => Some(PackMlCommand::Abort),
+            9 => Some(PackMlCommand::Clear),
+            _ => None,
+        }
+    }
+}
+
+impl PackMl {
+    /// returns true for the acting states that finish with state complete (SC)
+    pub fn is_acting(self) -> bool {
+        self.completed().is_some()
+    }
+
+    /// returns the state that follows on state complete (SC), `None` for wait states
+    pub fn completed(self) -> Option<PackMl> {
+        match self {
+            PackMl::Clearing | PackMl::Stopping => Some(PackMl::Stopped),
+            PackMl::Starting | PackMl::Unholding | PackMl::Unsuspending => Some(PackMl::Execute),
+            PackMl::Execute => Some(PackMl::Completing),
+            PackMl::Completing => Some(PackMl::Complete),
+            PackMl::Resetting => Some(PackMl::Idle),
+            PackMl::Holding => Some(PackMl::Held),
+            PackMl::Suspending => Some(PackMl::Suspended),
+            PackMl::Aborting => Some(PackMl::Aborted),
+            _ => None,
+        }
+    }
+
+    /// returns the target state of a command, `None` if the command is not valid in this state
+    pub fn command(self, command: PackMlCommand) -> Option<PackMl> {
+        use PackMl::*;
+        match (command, self) {
+            (PackMlCommand::Abort, Aborting | Aborted) => None,
+            (PackMlCommand::Abort, _) => Some(Aborting),
+            (PackMlCommand::Stop, Stopped | Stopping | Aborting | Aborted | Clearing) => None,
+            (PackMlCommand::Stop, _) => Some(Stopping),
+            (PackMlCommand::Clear, Aborted) => Some(Clearing),
+            (PackMlCommand::Reset, Stopped | Complete) => Some(Resetting),
+            (PackMlCommand::Start, Idle) => Some(Starting),
+            (PackMlCommand::Hold, Execute) => Some(Holding),
+            (PackMlCommand::Unhold, Held) => Some(Unholding),
+            (PackMlCommand::Suspend, Execute) => Some(Suspending),
+            (PackMlCommand::Unsuspend, Suspended) => Some(Unsuspending),
+            _ => None,
+        }
+    }
+}
+
+impl StateId for PackMl {
+    fn id(self) -> u16 {
+        self as u16
+    }
+}
+
+/// Waits for the next PackML transition of `state`.
+///
+/// Commands are read from `command` (a `PackMlCommand` number) and the variable is
+/// cleared once the command was read, invalid commands are discarded. In acting states
+/// the state complete (SC) transition fires when `complete` finishes, `complete` is
+/// ignored in wait states.
+pub async fn transitions(state: PackMl, command: &Var<u8>, complete: impl Future<Output = ()>) -> PackMl {
+    let mut target = None;
+    let wait_command = future::poll_fn(|_| {
+        poll_called();
+        let value = command.get();
+        if value == 0 {
+            return Poll::Pending;
+        }
+        command.set(0);
+        match PackMlCommand::from_u8(value).and_then(|c| state.command(c)) {
+            Some(next) => {
+                target = Some(next);
+                Poll::Ready(())
+            }
+            None => Poll::Pending,
+        }
+    });
+
+    match state.completed() {
+        Some(_) => either(wait_command, complete).await,
+        None => wait_command.await,
+    }
+    target.or(state.completed()).unwrap_or(state)
+}
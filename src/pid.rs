@@ -0,0 +1,238 @@
+//! PID controller with parameters in variables the host can tune.
+//!
+//! ```ignore
+//! static TEMP_PID: Pid = Pid::new();
+//!
+//! TEMP_PID.configure(2.0, 0.5, 0.1);
+//! TEMP_PID.set_limits(0.0, 100.0);
+//! loop_async! {{
+//!     TEMP_PID.pv.set(TEMPERATURE.get());
+//!     HEATER.set(TEMP_PID.update());
+//! }}
+//! ```
+//!
+//! The controller works in the parallel form `out = kp * e + ki * ∫e dt + kd * de/dt + ff`
+//! with the time in seconds taken from `time::current_time()`. The derivative acts on the
+//! process value only, so setpoint steps do not kick the output.
+
+use crate::sync::SyncCell;
+use crate::time::{current_time, wait_next_cycle};
+use crate::var::{Var, VarProps};
+
+pub struct Pid {
+    pub setpoint: Var<f32>,
+    /// process value
+    pub pv: Var<f32>,
+    pub out: Var<f32>,
+    /// added to the output, e.g. a value computed from the setpoint
+    pub feed_forward: Var<f32>,
+    pub kp: Var<f32>,
+    /// integral gain in 1/s
+    pub ki: Var<f32>,
+    /// derivative gain in s
+    pub kd: Var<f32>,
+    /// time constant of the derivative filter in s, 0 disables the filter
+    pub d_filter: Var<f32>,
+    /// the output is limited only if `out_min < out_max`
+    pub out_min: Var<f32>,
+    pub out_max: Var<f32>,
+    /// in manual mode the output follows `manual_out`
+    pub manual: Var<bool>,
+    /// tracks the output in automatic mode, so switching to manual is bumpless
+    pub manual_out: Var<f32>,
+    integral: SyncCell<f32>,
+    derivative: SyncCell<f32>,
+    last_pv: SyncCell<f32>,
+    last_time: SyncCell<Option<u64>>,
+}
+
+impl Pid {
+    pub const fn new() -> Pid {
+        Pid {
+            setpoint: Var::<f32>::new(),
+            pv: Var::<f32>::new(),
+            out: Var::<f32>::new(),
+            feed_forward: Var::<f32>::new(),
+            kp: Var::<f32>::new(),
+            ki: Var::<f32>::new(),
+            kd: Var::<f32>::new(),
+            d_filter: Var::<f32>::new(),
+            out_min: Var::<f32>::new(),
+            out_max: Var::<f32>::new(),
+            manual: Var::<bool>::new(),
+            manual_out: Var::<f32>::new(),
+            integral: SyncCell::new(0.0),
+            derivative: SyncCell::new(0.0),
+            last_pv: SyncCell::new(0.0),
+            last_time: SyncCell::new(None),
+        }
+    }
+
+    /// sets the gains
+    pub fn configure(&self, kp: f32, ki: f32, kd: f32) {
+        self.kp.set(kp);
+        self.ki.set(ki);
+        self.kd.set(kd);
+    }
+
+    /// sets the output limits
+    pub fn set_limits(&self, min: f32, max: f32) {
+        self.out_min.set(min);
+        self.out_max.set(max);
+    }
+
+    /// switches between manual and automatic mode, both directions are bumpless
+    pub fn set_manual(&self, manual: bool) {
+        self.manual.set(manual);
+    }
+
+    /// clears the integral and derivative state, the next update starts fresh
+    pub fn reset(&self) {
+        self.integral.set(0.0);
+        self.derivative.set(0.0);
+        self.last_time.set(None);
+    }
+
+    fn limit(&self, value: f32) -> f32 {
+        let (min, max) = (self.out_min.get(), self.out_max.get());
+        match min < max {
+            true => value.clamp(min, max),
+            false => value,
+        }
+    }
+
+    /// Computes the output from the current values, call it once per cycle.
+    /// Returns the new output, which is also written to `out`.
+    pub fn update(&self) -> f32 {
+        let now = current_time();
+        let pv = self.pv.get();
+        let dt = match self.last_time.get() {
+            // already updated in this cycle
+            Some(last) if now == last => return self.out.get(),
+            Some(last) if now > last => (now - last) as f32 / 1_000_000.0,
+            // first update, or the clock stepped back and the interval is unknown
+            _ => 0.0,
+        };
+        if dt > 0.0 {
+            let raw = -(pv - self.last_pv.get()) / dt;
+            let alpha = dt / (self.d_filter.get().max(0.0) + dt);
+            let derivative = self.derivative.get();
+            self.derivative.set(derivative + alpha * (raw - derivative));
+        }
+        self.last_time.set(Some(now));
+        self.last_pv.set(pv);
+
+        let error = self.setpoint.get() - pv;
+        let proportional = self.kp.get() * error;
+        let derivative = self.kd.get() * self.derivative.get();
+        let feed_forward = self.feed_forward.get();
+
+        let out = if self.manual.get() {
+            let out = self.limit(self.manual_out.get());
+            // preset the integral so switching back to automatic is bumpless
+            self.integral.set(out - proportional - derivative - feed_forward);
+            out
+        } else {
+            let previous = self.integral.get();
+            let integral = previous + self.ki.get() * error * dt;
+            let unlimited = proportional + integral + derivative + feed_forward;
+            let out = self.limit(unlimited);
+            // anti-windup: stop integrating while the output saturates in the same direction
+            let winding_up = (unlimited > out && integral > previous) || (unlimited < out && integral < previous);
+            if !winding_up {
+                self.integral.set(integral);
+            }
+            self.manual_out.set(out);
+            out
+        };
+        self.out.set(out);
+        out
+    }
+
+    /// Runs the controller each cycle, `input` sets the process value before the update
+    /// and `output` receives the new output.
+    pub async fn run(&self, mut input: impl FnMut(&Self), mut output: impl FnMut(f32)) {
+        loop {
+            input(self);
+            output(self.update());
+            wait_next_cycle().await;
+        }
+    }
+}
+
+impl Default for Pid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{lock_time, set_system_time};
+
+    fn update_at(pid: &Pid, time_ms: u64) -> f32 {
+        set_system_time(time_ms * 1000);
+        pid.update()
+    }
+
+    #[test]
+    fn anti_windup() {
+        let _time = lock_time();
+        let pid = Pid::new();
+        pid.configure(1.0, 10.0, 0.0);
+        pid.set_limits(0.0, 5.0);
+        pid.setpoint.set(10.0);
+        for cycle in 0..100 {
+            assert_eq!(update_at(&pid, cycle * 100), 5.0);
+        }
+        // the integral stopped growing, so the output follows the error right away
+        pid.pv.set(20.0);
+        assert_eq!(update_at(&pid, 10_000), 0.0);
+        assert!(pid.integral.get() < 20.0);
+    }
+
+    #[test]
+    fn derivative_acts_on_pv() {
+        let _time = lock_time();
+        let pid = Pid::new();
+        pid.configure(0.0, 0.0, 1.0);
+        update_at(&pid, 0);
+        // a setpoint step does not kick the output
+        pid.setpoint.set(100.0);
+        assert_eq!(update_at(&pid, 1_000), 0.0);
+        // the process value rises by 2 per second
+        pid.pv.set(2.0);
+        assert_eq!(update_at(&pid, 2_000), -2.0);
+    }
+
+    #[test]
+    fn bumpless_manual_to_auto() {
+        let _time = lock_time();
+        let pid = Pid::new();
+        pid.configure(2.0, 1.0, 0.0);
+        pid.setpoint.set(10.0);
+        pid.pv.set(8.0);
+        pid.set_manual(true);
+        pid.manual_out.set(30.0);
+        assert_eq!(update_at(&pid, 0), 30.0);
+        pid.set_manual(false);
+        // only the integral of the new cycle is added
+        assert_eq!(update_at(&pid, 1), 30.0 + 2.0 * 0.001);
+    }
+
+    #[test]
+    fn clock_stepped_back() {
+        let _time = lock_time();
+        let pid = Pid::new();
+        pid.configure(1.0, 1.0, 0.0);
+        pid.setpoint.set(1.0);
+        assert_eq!(update_at(&pid, 1_000), 1.0);
+        // the same cycle returns the previous output
+        pid.setpoint.set(2.0);
+        assert_eq!(update_at(&pid, 1_000), 1.0);
+        // after the clock stepped back the controller keeps running
+        assert_eq!(update_at(&pid, 0), 2.0);
+        assert_eq!(update_at(&pid, 1_000), 4.0);
+    }
+}
@@ -1,44 +1,179 @@
-pub static mut POLL_CALLED: bool = false;
+use crate::sync::SyncCell;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures::future;
 
-/// async loop, avoids blocking and lets other async 
+/// Bookkeeping of a task polled by the cycle executor.
+///
+/// The executor polls every task through `Task::poll`, so `loop_async!` can tell
+/// whether the body of *this* task awaited something, independent of other tasks.
+pub struct Task {
+    /// a wait future was polled since the last check in `loop_async!`
+    awaited: SyncCell<bool>,
+    /// loop iterations without await per cycle
+    budget: SyncCell<u16>,
+    remaining: SyncCell<u16>,
+}
+
+/// used for code that is not polled through `Task::poll`
+static DEFAULT_TASK: Task = Task::new();
+
+static CURRENT_TASK: SyncCell<Option<&'static Task>> = SyncCell::new(None);
+
+impl Task {
+    pub const fn new() -> Task {
+        Task::with_budget(1)
+    }
+
+    /// Creates a task whose `loop_async!` body may run up to `budget` iterations
+    /// per cycle as long as it does not await anything.
+    pub const fn with_budget(budget: u16) -> Task {
+        Task {
+            awaited: SyncCell::new(false),
+            budget: SyncCell::new(budget),
+            remaining: SyncCell::new(budget),
+        }
+    }
+
+    /// sets the number of loop iterations without await per cycle
+    pub fn set_budget(&self, budget: u16) {
+        self.budget.set(budget);
+    }
+
+    /// Polls the future of this task, must be called by the executor once per cycle.
+    pub fn poll<F: Future + ?Sized>(&'static self, future: Pin<&mut F>, cx: &mut Context) -> Poll<F::Output> {
+        let previous = CURRENT_TASK.replace(Some(self));
+        self.remaining.set(self.budget.get());
+        let result = future.poll(cx);
+        CURRENT_TASK.set(previous);
+        result
+    }
+}
+
+impl Default for Task {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// returns the task that is currently polled
+pub fn current_task() -> &'static Task {
+    CURRENT_TASK.get().unwrap_or(&DEFAULT_TASK)
+}
+
+/// async loop, avoids blocking and lets other async
 /// tasks make progess.
 /// This macro requires double-curly braces.
 ///
+/// If the body did not await anything, the loop waits for the next cycle
+/// once the iteration budget of the task (see `Task::with_budget`) is used up.
+///
 /// # Example
-/// 
+///
 /// ```
 /// loop_async! {{
 ///   //your async task code
 /// }}
 /// ```
-
 #[macro_export]
 macro_rules! loop_async {
-    {$body:block} => { 
+    {$body:block} => {
         loop {
             $body
 
-            if pilot_sys::poll::await_next_cycle_needed() { 
+            if pilot_sys::poll::await_next_cycle_needed() {
                 pilot_sys::time::wait_next_cycle().await;
+                pilot_sys::poll::cycle_started();
             }
-        } 
+        }
     }
 }
 
+/// Returns true if the current task did not await anything since the last call
+/// and its iteration budget for this cycle is used up.
 #[inline(always)]
 pub fn await_next_cycle_needed() -> bool {
-    unsafe {
-        if !POLL_CALLED {
-            return true;
-        }
-        POLL_CALLED = false;
+    let task = current_task();
+    if task.awaited.replace(false) {
+        return false;
     }
-    false
+    let remaining = task.remaining.get().saturating_sub(1);
+    task.remaining.set(remaining);
+    remaining == 0
+}
+
+/// Resets the bookkeeping of the current task after it waited for the next cycle.
+#[inline(always)]
+pub fn cycle_started() {
+    let task = current_task();
+    task.awaited.set(false);
+    task.remaining.set(task.budget.get());
 }
 
+/// Marks that the current task awaited something,
+/// must be called by every future that waits for a condition.
 #[inline(always)]
 pub fn poll_called() {
-    unsafe {
-        POLL_CALLED = true;
+    current_task().awaited.set(true);
+}
+
+/// Yields to the other tasks, the current task continues in the next cycle.
+pub async fn yield_now() {
+    let mut yielded = false;
+    future::poll_fn(|_| {
+        poll_called();
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::set_system_time;
+    use futures::task::noop_waker_ref;
+
+    static BUSY: Task = Task::with_budget(3);
+    static WAITING: Task = Task::with_budget(3);
+
+    #[test]
+    fn loop_budget_per_task() {
+        let _time = crate::time::lock_time();
+        let busy_runs = SyncCell::new(0);
+        let waiting_runs = SyncCell::new(0);
+        let busy = async {
+            loop_async! {{
+                busy_runs.set(busy_runs.get() + 1);
+            }}
+        };
+        // awaiting in the body does not use up the budget, but yields every cycle
+        let waiting = async {
+            loop_async! {{
+                waiting_runs.set(waiting_runs.get() + 1);
+                yield_now().await;
+            }}
+        };
+        futures::pin_mut!(busy);
+        futures::pin_mut!(waiting);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for cycle in 1..=3 {
+            set_system_time(cycle * 1_000);
+            assert!(BUSY.poll(busy.as_mut(), &mut cx).is_pending());
+            assert!(WAITING.poll(waiting.as_mut(), &mut cx).is_pending());
+            assert_eq!(busy_runs.get(), cycle * 3);
+            assert_eq!(waiting_runs.get(), cycle);
+        }
+        BUSY.set_budget(1);
+        set_system_time(4_000);
+        let _ = BUSY.poll(busy.as_mut(), &mut cx);
+        assert_eq!(busy_runs.get(), 10);
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,5 @@
+use crate::binlog::FRAME_START;
+use crate::log::{LogSink, Record};
 use core::fmt;
 pub struct SerialWriter;
 
@@ -6,40 +8,97 @@ impl fmt::Write for SerialWriter {
         unsafe {
             for c in s.as_bytes() { // we use bytes here instead of chars to print unicode characters out as well
                 _putchar(*c);
+                if *c == FRAME_START {
+                    // escaped, so the text is not taken for a binlog frame
+                    _putchar(0);
+                }
             }
         }
         Ok(())
     }
 }
 
+/// Writes log output through `_putchar`, the default `LogSink`.
+pub struct PutcharSink;
+
+impl LogSink for PutcharSink {
+    fn log(&self, record: &Record) {
+        // an empty println!() only ends the line
+        if record.args.as_str() != Some("") {
+            unsafe { _putchar(0x27); } // start of logstring
+            if let Some(level) = record.level {
+                let _ = fmt::write(&mut SerialWriter, format_args!("[{} {}] ", level.as_str(), record.module));
+            }
+            let _ = fmt::write(&mut SerialWriter, record.args);
+        }
+        if record.newline {
+            unsafe {
+                _putchar(10);
+                _putchar(13);
+            }
+        }
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        for c in bytes {
+            unsafe { _putchar(*c); }
+        }
+    }
+}
+
+/// Writes log output to stdout, the default `LogSink` on the host and in tests.
+#[cfg(any(test, feature = "std"))]
+pub struct StdoutSink;
+
+#[cfg(any(test, feature = "std"))]
+impl LogSink for StdoutSink {
+    fn log(&self, record: &Record) {
+        use std::io::Write;
+        let mut stdout = std::io::stdout().lock();
+        if let Some(level) = record.level {
+            let _ = write!(stdout, "[{} {}] ", level.as_str(), record.module);
+        }
+        let _ = stdout.write_fmt(record.args);
+        if record.newline {
+            let _ = writeln!(stdout);
+        }
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        use std::io::Write;
+        let _ = std::io::stdout().lock().write_all(bytes);
+    }
+}
+
+/// Writes `print!` and `println!` output to the log sink.
+pub fn _print(args: fmt::Arguments, newline: bool) {
+    crate::log::sink().log(&Record {
+        level: None,
+        module: "",
+        args,
+        newline,
+    });
+}
+
 #[macro_export]
 macro_rules! print {
     () => ();
-    ($($arg:tt)*) => ({ 
-      unsafe { crate::print::_putchar(0x27); } // start of logstring
-      core::fmt::write(&mut crate::print::SerialWriter, format_args!($($arg)*)).unwrap(); 
+    ($($arg:tt)*) => ({
+      $crate::print::_print(format_args!($($arg)*), false);
     });
 }
 
 #[macro_export]
 macro_rules! println {
     () => ({
-      unsafe {
-        crate::print::_putchar(10); 
-        crate::print::_putchar(13);
-      }
+      $crate::print::_print(format_args!(""), true);
     });
-    ($($arg:tt)*) => ({ 
-      unsafe { crate::print::_putchar(0x27); } // start of logstring
-      core::fmt::write(&mut crate::print::SerialWriter, format_args!($($arg)*)).unwrap(); 
-      unsafe {
-        crate::print::_putchar(10); 
-        crate::print::_putchar(13);
-      }
+    ($($arg:tt)*) => ({
+      $crate::print::_print(format_args!($($arg)*), true);
     });
 }
 
 // needed by print macros
 extern "C" {
     pub fn _putchar(c: u8);
-}
\ No newline at end of file
+}
@@ -0,0 +1,438 @@
+//! Recipes: named, validated parameter sets that are applied in one step.
+//!
+//! ```ignore
+//! static RECIPES_STORE: RecipeStore<3, 8> = RecipeStore::new();
+//! static RECIPES: RecipeSet<'static, 3, 8> = RecipeSet::new(
+//!     [
+//!         Param::new("fill volume", &FILL_VOLUME, 0.0, 500.0),
+//!         Param::new("mix time", &MIX_TIME, 1.0, 600.0).integer(),
+//!         Param::new("temperature", &TEMPERATURE_SP, 20.0, 95.0),
+//!     ],
+//!     &RECIPES_STORE,
+//! );
+//!
+//! RECIPES.store(1, "standard", [250.0, 120.0, 60.0])?;
+//! RECIPES.select(1)?;
+//! loop_async! {{
+//!     RECIPES.cycle(); // applies a selected recipe before the tasks run
+//! }}
+//! ```
+//!
+//! The `RecipeStore` only holds plain data and can be placed in RAM that is retained over
+//! a reset (see the linker script). Slots with a wrong checksum are treated as empty.
+//! The host reads and writes whole recipes through `MemVar`, the subvalue selects the
+//! recipe id, and selects a recipe by writing its id to `RecipeSet::load`.
+
+use crate::sync::SyncCell;
+use crate::var::{MemVar, ScalarVar, Var, VarProps};
+
+const MAGIC: u32 = 0x5243_5045;
+
+/// Maximum length of a recipe name.
+pub const NAME_LEN: usize = 16;
+
+/// A parameter of a recipe with its valid range.
+pub struct Param<'a> {
+    name: &'static str,
+    var: &'a dyn ScalarVar,
+    min: f64,
+    max: f64,
+    integer: bool,
+}
+
+impl<'a> Param<'a> {
+    pub const fn new(name: &'static str, var: &'a dyn ScalarVar, min: f64, max: f64) -> Self {
+        Param {
+            name,
+            var,
+            min,
+            max,
+            integer: false,
+        }
+    }
+
+    /// only whole numbers are valid, use it for integer and bool variables so a value
+    /// is rejected instead of truncated
+    pub const fn integer(mut self) -> Self {
+        self.integer = true;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_valid(&self, value: f64) -> bool {
+        value >= self.min && value <= self.max && (!self.integer || value as i64 as f64 == value)
+    }
+}
+
+/// Reason a recipe could not be stored or applied.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RecipeError {
+    /// the id is not in `1..=R`
+    InvalidId,
+    /// no valid recipe is stored under the id
+    Empty,
+    /// the value of the parameter with this index is out of range or not a whole number
+    /// for an integer parameter
+    OutOfRange(u16),
+}
+
+impl RecipeError {
+    /// status code for `RecipeSet::status`
+    pub fn code(self) -> u8 {
+        match self {
+            RecipeError::InvalidId => 1,
+            RecipeError::Empty => 2,
+            RecipeError::OutOfRange(_) => 3,
+        }
+    }
+}
+
+/// A stored recipe with `P` parameter values.
+#[derive(Copy, Clone)]
+pub struct Recipe<const P: usize> {
+    magic: u32,
+    name_len: u8,
+    name: [u8; NAME_LEN],
+    pub values: [f64; P],
+    checksum: u32,
+}
+
+impl<const P: usize> Recipe<P> {
+    const EMPTY: Recipe<P> = Recipe {
+        magic: 0,
+        name_len: 0,
+        name: [0; NAME_LEN],
+        values: [0.0; P],
+        checksum: 0,
+    };
+
+    /// creates a recipe, longer names are truncated
+    pub fn new(name: &str, values: [f64; P]) -> Self {
+        let name = &name.as_bytes()[..name.len().min(NAME_LEN)];
+        let mut recipe = Recipe {
+            magic: MAGIC,
+            name_len: name.len() as u8,
+            values,
+            ..Recipe::EMPTY
+        };
+        recipe.name[..name.len()].copy_from_slice(name);
+        recipe.checksum = recipe.compute_checksum();
+        recipe
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..(self.name_len as usize).min(NAME_LEN)]
+    }
+
+    /// checksum over the values, as exposed in `RecipeSet::checksum`
+    pub fn values_checksum(&self) -> u32 {
+        self.values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .fold(0x811c_9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        self.name()
+            .iter()
+            .fold(self.values_checksum(), |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.compute_checksum()
+    }
+}
+
+/// Storage for `R` recipes with `P` parameters each.
+pub struct RecipeStore<const P: usize, const R: usize> {
+    slots: [SyncCell<Recipe<P>>; R],
+}
+
+impl<const P: usize, const R: usize> RecipeStore<P, R> {
+    pub const fn new() -> Self {
+        RecipeStore {
+            slots: [const { SyncCell::new(Recipe::EMPTY) }; R],
+        }
+    }
+}
+
+impl<const P: usize, const R: usize> Default for RecipeStore<P, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The parameters of a recipe and the stored recipes, with ids `1..=R`.
+pub struct RecipeSet<'a, const P: usize, const R: usize> {
+    params: [Param<'a>; P],
+    store: &'a RecipeStore<P, R>,
+    staged: SyncCell<Option<(u16, Recipe<P>)>>,
+    /// parameter values read back after the active recipe was applied
+    applied: SyncCell<[f64; P]>,
+    /// id of the applied recipe, 0 if none
+    pub active: Var<u16>,
+    /// `Recipe::values_checksum` of the applied recipe
+    pub checksum: Var<u32>,
+    /// result of the last operation, 0 if ok, otherwise `RecipeError::code`
+    pub status: Var<u8>,
+    /// true if a parameter was changed after the recipe was applied
+    pub modified: Var<bool>,
+    /// host command: id of the recipe to apply, reset to 0 when done
+    pub load: Var<u16>,
+}
+
+impl<'a, const P: usize, const R: usize> RecipeSet<'a, P, R> {
+    pub const fn new(params: [Param<'a>; P], store: &'a RecipeStore<P, R>) -> Self {
+        // a recipe must fit into a MemVar buffer
+        assert!(P > 0 && 1 + NAME_LEN + 8 * P <= u8::MAX as usize);
+        RecipeSet {
+            params,
+            store,
+            staged: SyncCell::new(None),
+            applied: SyncCell::new([0.0; P]),
+            active: Var::<u16>::new(),
+            checksum: Var::<u32>::new(),
+            status: Var::<u8>::new(),
+            modified: Var::<bool>::new(),
+            load: Var::<u16>::new(),
+        }
+    }
+
+    pub fn param(&self, index: usize) -> Option<&Param<'a>> {
+        self.params.get(index)
+    }
+
+    fn slot(&self, id: u16) -> Result<&SyncCell<Recipe<P>>, RecipeError> {
+        match id {
+            0 => Err(RecipeError::InvalidId),
+            id => self.store.slots.get(id as usize - 1).ok_or(RecipeError::InvalidId),
+        }
+    }
+
+    fn validate(&self, values: &[f64; P]) -> Result<(), RecipeError> {
+        match self.params.iter().zip(values).position(|(param, value)| !param.is_valid(*value)) {
+            Some(index) => Err(RecipeError::OutOfRange(index as u16)),
+            None => Ok(()),
+        }
+    }
+
+    fn report<T>(&self, result: Result<T, RecipeError>) -> Result<T, RecipeError> {
+        self.status.set(match &result {
+            Ok(_) => 0,
+            Err(error) => error.code(),
+        });
+        result
+    }
+
+    /// returns the recipe stored under `id`
+    pub fn get(&self, id: u16) -> Result<Recipe<P>, RecipeError> {
+        let recipe = self.slot(id)?.get();
+        match recipe.is_valid() {
+            true => Ok(recipe),
+            false => Err(RecipeError::Empty),
+        }
+    }
+
+    /// validates and stores a recipe under `id`
+    pub fn store(&self, id: u16, name: &str, values: [f64; P]) -> Result<(), RecipeError> {
+        let result = self.validate(&values).and_then(|_| self.slot(id));
+        let slot = self.report(result)?;
+        slot.set(Recipe::new(name, values));
+        Ok(())
+    }
+
+    /// stores the current values of the parameters as recipe `id`
+    pub fn capture(&self, id: u16, name: &str) -> Result<(), RecipeError> {
+        let mut values = [0.0; P];
+        for (value, param) in values.iter_mut().zip(self.params.iter()) {
+            *value = param.var.get_f64();
+        }
+        self.store(id, name, values)
+    }
+
+    pub fn delete(&self, id: u16) -> Result<(), RecipeError> {
+        let slot = self.report(self.slot(id))?;
+        slot.set(Recipe::EMPTY);
+        Ok(())
+    }
+
+    /// Validates recipe `id` and applies it with the next `cycle`. The recipe is copied,
+    /// so changing the stored recipe afterwards does not affect the selection.
+    pub fn select(&self, id: u16) -> Result<(), RecipeError> {
+        let result = self
+            .get(id)
+            .and_then(|recipe| self.validate(&recipe.values).map(|_| recipe));
+        let recipe = self.report(result)?;
+        self.staged.set(Some((id, recipe)));
+        Ok(())
+    }
+
+    /// returns true if a selected recipe waits for the next `cycle`
+    pub fn is_pending(&self) -> bool {
+        self.staged.get().is_some()
+    }
+
+    /// returns true if a parameter differs from the value it got when the active recipe
+    /// was applied, or the stored recipe changed since then
+    fn is_modified(&self, id: u16) -> bool {
+        let stored = self.get(id).map(|recipe| recipe.values_checksum());
+        stored != Ok(self.checksum.get())
+            || self
+                .params
+                .iter()
+                .zip(self.applied.get().iter())
+                .any(|(param, value)| param.var.get_f64() != *value)
+    }
+
+    /// Applies a selected recipe and executes host commands. Call it once per cycle
+    /// before the tasks run, so all parameters change between two cycles.
+    pub fn cycle(&self) {
+        let load = self.load.get();
+        if load != 0 {
+            let _ = self.select(load);
+            self.load.set(0);
+        }
+        if let Some((id, recipe)) = self.staged.take() {
+            let mut applied = [0.0; P];
+            for ((param, value), applied) in self.params.iter().zip(recipe.values.iter()).zip(applied.iter_mut()) {
+                param.var.set_f64(*value);
+                // compare with the value as stored in the variable, e.g. rounded to f32
+                *applied = param.var.get_f64();
+            }
+            self.applied.set(applied);
+            self.active.set(id);
+            self.checksum.set(recipe.values_checksum());
+        }
+        let modified = match self.active.get() {
+            0 => false,
+            id => self.is_modified(id),
+        };
+        self.modified.set(modified);
+    }
+}
+
+impl<const P: usize, const R: usize> MemVar for RecipeSet<'_, P, R> {
+    /// writes the recipe with the id given as subvalue (name length u8, name with
+    /// `NAME_LEN` bytes, values f64 each), subvalue 0 writes the current parameter values
+    /// with the name of the active recipe, returns 0 for an empty slot
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        let recipe = match subvalue {
+            0 => {
+                let mut recipe = self.get(self.active.get()).unwrap_or(Recipe::EMPTY);
+                for (value, param) in recipe.values.iter_mut().zip(self.params.iter()) {
+                    *value = param.var.get_f64();
+                }
+                recipe
+            }
+            id => match self.get(id as u16) {
+                Ok(recipe) => recipe,
+                Err(_) => return 0,
+            },
+        };
+        let bytes = [recipe.name_len].into_iter()
+            .chain(recipe.name)
+            .chain(recipe.values.iter().flat_map(|value| value.to_le_bytes()));
+        let mut len = 0;
+        for byte in bytes {
+            *buffer.add(len) = byte;
+            len += 1;
+        }
+        len as u8
+    }
+
+    /// stores a recipe in the format of `to_buffer` under the id given as subvalue,
+    /// the result is reported in `status`
+    unsafe fn from_buffer(&self, buffer: *const u8, subvalue: u8) -> u8 {
+        let name_len = (*buffer as usize).min(NAME_LEN);
+        let name = core::slice::from_raw_parts(buffer.add(1), name_len);
+        let mut values = [0.0; P];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = (buffer.add(1 + NAME_LEN + i * 8) as *const f64).read_unaligned();
+        }
+        let _ = self.store(subvalue as u16, core::str::from_utf8(name).unwrap_or(""), values);
+        (1 + NAME_LEN + 8 * P) as u8
+    }
+
+    unsafe fn is_dirty(&self) -> bool {
+        false
+    }
+
+    unsafe fn clear_dirty(&self) {}
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_subscribed(&self, _value: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_is_not_modified() {
+        let setpoint = Var::<f32>::new();
+        let count = Var::<u16>::new();
+        let store = RecipeStore::<2, 2>::new();
+        let recipes = RecipeSet::new(
+            [
+                Param::new("setpoint", &setpoint, 0.0, 10.0),
+                Param::new("count", &count, 0.0, 100.0).integer(),
+            ],
+            &store,
+        );
+        recipes.store(1, "standard", [0.1, 2.0]).unwrap();
+        recipes.select(1).unwrap();
+        assert!(recipes.is_pending());
+        recipes.cycle();
+        assert_eq!(recipes.active.get(), 1);
+        assert_eq!(setpoint.get(), 0.1);
+        assert_eq!(count.get(), 2);
+        assert!(!recipes.modified.get());
+
+        count.set(3);
+        recipes.cycle();
+        assert!(recipes.modified.get());
+        recipes.load.set(1);
+        recipes.cycle();
+        assert!(!recipes.modified.get());
+        assert_eq!(recipes.load.get(), 0);
+
+        // changing the stored recipe makes the applied one outdated
+        recipes.store(1, "standard", [0.2, 2.0]).unwrap();
+        recipes.cycle();
+        assert!(recipes.modified.get());
+    }
+
+    #[test]
+    fn validation() {
+        let setpoint = Var::<f32>::new();
+        let count = Var::<u16>::new();
+        let store = RecipeStore::<2, 2>::new();
+        let recipes = RecipeSet::new(
+            [
+                Param::new("setpoint", &setpoint, 0.0, 10.0),
+                Param::new("count", &count, 0.0, 100.0).integer(),
+            ],
+            &store,
+        );
+        assert_eq!(recipes.store(1, "half", [1.0, 2.5]), Err(RecipeError::OutOfRange(1)));
+        assert_eq!(recipes.store(1, "hot", [11.0, 2.0]), Err(RecipeError::OutOfRange(0)));
+        assert_eq!(recipes.status.get(), RecipeError::OutOfRange(0).code());
+        assert_eq!(recipes.store(3, "id", [1.0, 2.0]), Err(RecipeError::InvalidId));
+        assert_eq!(recipes.select(2), Err(RecipeError::Empty));
+        assert_eq!(recipes.store(2, "ok", [1.0, 2.0]), Ok(()));
+        assert_eq!(recipes.status.get(), 0);
+        assert_eq!(recipes.get(2).unwrap().name(), b"ok");
+    }
+}
@@ -0,0 +1,326 @@
+//! A RAM ring buffer that keeps the last log records for the host.
+//!
+//! ```ignore
+//! static LOG: RingLog<32, 80> = RingLog::new();
+//!
+//! // in a RAM section that is not cleared on reset, see the linker script
+//! #[link_section = ".uninit.retained_log"]
+//! static RETAINED_LOG: RingLog<32, 80> = RingLog::new();
+//!
+//! log::set_sink(&LOG);
+//! // nothing is saved automatically, the panic hook must copy the records
+//! fault::set_panic_hook(|| LOG.save_to(&RETAINED_LOG));
+//! // after the next boot
+//! if RETAINED_LOG.is_valid() { /* read the records */ }
+//! ```
+//!
+//! The host reads the records incrementally through `MemVar`: it writes the sequence
+//! number to start at with `from_buffer` and reads one record per `to_buffer` call.
+
+use crate::log::{LogSink, Record};
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::MemVar;
+use core::fmt::{self, Write};
+
+const MAGIC: u32 = 0x524c_4f47;
+
+/// Size of a record in the `MemVar` buffer before the message.
+pub const ENTRY_HEADER: usize = 14;
+
+/// A log record with a message of up to `M` bytes, longer messages are truncated.
+#[derive(Copy, Clone)]
+pub struct LogEntry<const M: usize> {
+    pub seq: u32,
+    /// system time in microseconds
+    pub timestamp: u64,
+    /// `log::Level` as number, 0 for `print!` and `println!`
+    pub level: u8,
+    len: u8,
+    message: [u8; M],
+}
+
+impl<const M: usize> LogEntry<M> {
+    const EMPTY: LogEntry<M> = LogEntry {
+        seq: 0,
+        timestamp: 0,
+        level: 0,
+        len: 0,
+        message: [0; M],
+    };
+
+    pub fn message(&self) -> &[u8] {
+        // a retained entry may hold garbage
+        &self.message[..(self.len as usize).min(M)]
+    }
+
+    fn checksum(&self, hash: u32) -> u32 {
+        let header = self.seq.to_le_bytes().into_iter()
+            .chain(self.timestamp.to_le_bytes())
+            .chain([self.level, self.len]);
+        header.chain(self.message)
+            .fold(hash, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+    }
+}
+
+impl<const M: usize> Write for LogEntry<M> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.len as usize;
+        let count = s.len().min(M.saturating_sub(len));
+        self.message[len..len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count as u8;
+        Ok(())
+    }
+}
+
+/// Keeps the last `N` log records with messages of up to `M` bytes (at most 241).
+pub struct RingLog<const N: usize, const M: usize> {
+    entries: [SyncCell<LogEntry<M>>; N],
+    /// sequence number of the next record
+    next_seq: SyncCell<u32>,
+    /// the last record is continued by the next one (`print!` without newline)
+    open: SyncCell<bool>,
+    /// next sequence number read by the host
+    cursor: SyncCell<u32>,
+    magic: SyncCell<u32>,
+    checksum: SyncCell<u32>,
+}
+
+impl<const N: usize, const M: usize> RingLog<N, M> {
+    pub const fn new() -> Self {
+        assert!(N > 0 && M + ENTRY_HEADER <= u8::MAX as usize);
+        RingLog {
+            entries: [const { SyncCell::new(LogEntry::EMPTY) }; N],
+            next_seq: SyncCell::new(0),
+            open: SyncCell::new(false),
+            cursor: SyncCell::new(0),
+            magic: SyncCell::new(0),
+            checksum: SyncCell::new(0),
+        }
+    }
+
+    /// returns the sequence number of the oldest record that is still stored
+    pub fn oldest_seq(&self) -> u32 {
+        self.next_seq.get().saturating_sub(N as u32)
+    }
+
+    /// returns the sequence number the next record will get
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq.get()
+    }
+
+    /// returns the record with sequence number `seq`, or the oldest stored record if
+    /// `seq` was already overwritten
+    pub fn read(&self, seq: u32) -> Option<LogEntry<M>> {
+        let seq = seq.max(self.oldest_seq());
+        if seq >= self.next_seq.get() {
+            return None;
+        }
+        Some(self.entries[seq as usize % N].get())
+    }
+
+    /// removes all records and invalidates a saved copy
+    pub fn clear(&self) {
+        self.next_seq.set(0);
+        self.cursor.set(0);
+        self.open.set(false);
+        self.magic.set(0);
+    }
+
+    fn checksum(&self) -> u32 {
+        let hash = self.next_seq.get().wrapping_mul(0x0100_0193) ^ 0x811c_9dc5;
+        self.entries.iter().fold(hash, |hash, entry| entry.get().checksum(hash))
+    }
+
+    /// Copies all records to `retained`, e.g. a `RingLog` in RAM that survives a reset.
+    /// Call it from the panic handler, e.g. through `fault::set_panic_hook`, the
+    /// records are not saved otherwise.
+    pub fn save_to(&self, retained: &Self) {
+        for (entry, saved) in self.entries.iter().zip(retained.entries.iter()) {
+            saved.set(entry.get());
+        }
+        retained.next_seq.set(self.next_seq.get());
+        retained.cursor.set(retained.oldest_seq());
+        retained.open.set(false);
+        retained.checksum.set(retained.checksum());
+        retained.magic.set(MAGIC);
+    }
+
+    /// returns true if the records were saved with `save_to` and are intact,
+    /// use it after a reset before reading a retained copy
+    pub fn is_valid(&self) -> bool {
+        self.magic.get() == MAGIC && self.checksum.get() == self.checksum()
+    }
+
+    fn push(&self, level: u8, args: fmt::Arguments, newline: bool) {
+        let seq = self.next_seq.get();
+        let slot = match self.open.get() && seq > 0 {
+            true => &self.entries[(seq - 1) as usize % N],
+            false => {
+                self.next_seq.set(seq + 1);
+                let slot = &self.entries[seq as usize % N];
+                slot.set(LogEntry {
+                    seq,
+                    timestamp: current_time(),
+                    level,
+                    ..LogEntry::EMPTY
+                });
+                slot
+            }
+        };
+        let mut entry = slot.get();
+        let _ = entry.write_fmt(args);
+        slot.set(entry);
+        self.open.set(!newline);
+    }
+}
+
+impl<const N: usize, const M: usize> Default for RingLog<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const M: usize> LogSink for RingLog<N, M> {
+    fn log(&self, record: &Record) {
+        match record.level {
+            Some(level) => {
+                // leveled records always start a new entry
+                self.open.set(false);
+                self.push(level as u8, record.args, record.newline)
+            }
+            None => self.push(0, record.args, record.newline),
+        }
+    }
+}
+
+impl<const N: usize, const M: usize> MemVar for RingLog<N, M> {
+    /// subvalue 0 writes the next unread record (seq u32, timestamp u64, level u8,
+    /// length u8, message) and returns 0 if there is none, subvalue 1 writes the oldest
+    /// and the next sequence number (2 x u32)
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        match subvalue {
+            1 => {
+                let status = [self.oldest_seq().to_le_bytes(), self.next_seq.get().to_le_bytes()];
+                core::ptr::copy_nonoverlapping(status.as_ptr() as *const u8, buffer, 8);
+                8
+            }
+            _ => match self.read(self.cursor.get()) {
+                Some(entry) => {
+                    self.cursor.set(entry.seq + 1);
+                    let message = entry.message();
+                    let header = entry.seq.to_le_bytes().into_iter()
+                        .chain(entry.timestamp.to_le_bytes())
+                        .chain([entry.level, message.len() as u8]);
+                    for (i, byte) in header.chain(message.iter().copied()).enumerate() {
+                        *buffer.add(i) = byte;
+                    }
+                    (ENTRY_HEADER + message.len()) as u8
+                }
+                None => 0,
+            },
+        }
+    }
+
+    /// sets the sequence number of the next record to read (u32)
+    unsafe fn from_buffer(&self, buffer: *const u8, _subvalue: u8) -> u8 {
+        self.cursor.set((buffer as *const u32).read_unaligned());
+        4
+    }
+
+    /// true while there are unread records
+    unsafe fn is_dirty(&self) -> bool {
+        self.cursor.get() < self.next_seq.get()
+    }
+
+    unsafe fn clear_dirty(&self) {}
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_subscribed(&self, _value: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Level;
+    use crate::var::VarBuffer;
+
+    fn log(ring: &RingLog<3, 8>, level: Option<Level>, args: fmt::Arguments, newline: bool) {
+        ring.log(&Record {
+            level,
+            module: "",
+            args,
+            newline,
+        });
+    }
+
+    #[test]
+    fn wraps_around() {
+        let ring = RingLog::<3, 8>::new();
+        for i in 0..5 {
+            log(&ring, Some(Level::Info), format_args!("record {}", i), true);
+        }
+        assert_eq!((ring.oldest_seq(), ring.next_seq()), (2, 5));
+        // overwritten records are skipped
+        let entry = ring.read(0).unwrap();
+        assert_eq!((entry.seq, entry.message()), (2, &b"record 2"[..]));
+        assert!(ring.read(5).is_none());
+
+        let mut buffer = VarBuffer::new();
+        unsafe {
+            assert!(ring.is_dirty());
+            assert_eq!(ring.to_buffer(buffer.as_mut_ptr(), 0) as usize, ENTRY_HEADER + 8);
+            assert_eq!(&buffer.0[..4], &2u32.to_le_bytes());
+            assert_eq!(&buffer.0[ENTRY_HEADER..ENTRY_HEADER + 8], b"record 2");
+            ring.to_buffer(buffer.as_mut_ptr(), 0);
+            ring.to_buffer(buffer.as_mut_ptr(), 0);
+            assert!(!ring.is_dirty());
+            assert_eq!(ring.to_buffer(buffer.as_mut_ptr(), 0), 0);
+        }
+    }
+
+    #[test]
+    fn print_continues_and_truncates() {
+        let ring = RingLog::<3, 8>::new();
+        log(&ring, None, format_args!("abc"), false);
+        log(&ring, None, format_args!("defghij"), true);
+        log(&ring, Some(Level::Warn), format_args!("w"), true);
+        assert_eq!(ring.next_seq(), 2);
+        assert_eq!(ring.read(0).unwrap().message(), b"abcdefgh");
+        assert_eq!(ring.read(1).unwrap().level, Level::Warn as u8);
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let ring = RingLog::<3, 8>::new();
+        let retained = RingLog::<3, 8>::new();
+        assert!(!retained.is_valid());
+        for i in 0..4 {
+            log(&ring, Some(Level::Error), format_args!("{}", i), true);
+        }
+        ring.save_to(&retained);
+        assert!(retained.is_valid());
+        assert_eq!(retained.read(0).unwrap().message(), b"1");
+        assert!(unsafe { retained.is_dirty() });
+
+        // corrupted records are detected
+        let mut entry = retained.entries[0].get();
+        entry.len = u8::MAX;
+        assert_eq!(entry.message().len(), 8);
+        retained.entries[0].set(entry);
+        assert!(!retained.is_valid());
+
+        ring.save_to(&retained);
+        retained.clear();
+        assert!(!retained.is_valid());
+    }
+}
@@ -0,0 +1,170 @@
+//! Task classes with their own cycle period and priority, as in classic PLC task
+//! configurations.
+//!
+//! ```ignore
+//! static FAST: TaskClass = TaskClass::new("fast", 0, 1_000);
+//! static COMM: TaskClass = TaskClass::new("comm", 1, 100_000);
+//! static LOG: TaskClass = TaskClass::new("log", 2, 1_000_000);
+//! static SCHEDULER: Scheduler<'static, 3> = Scheduler::new([&LOG, &FAST, &COMM]);
+//!
+//! // runtime, every base cycle
+//! set_system_time(timer_us());
+//! SCHEDULER.run_cycle(800, timer_us, |class| {
+//!     if class.is(&FAST) { poll_fast_tasks() }
+//!     else if class.is(&COMM) { poll_comm_tasks() }
+//!     else { poll_log_tasks() }
+//! });
+//! ```
+
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{Var, VarProps};
+
+/// A named class of tasks that are polled with a common period.
+pub struct TaskClass {
+    name: &'static str,
+    priority: u8,
+    period_us: SyncCell<u32>,
+    /// number of times the class was polled
+    pub runs: Var<u32>,
+    /// number of times the class was due but skipped because the cycle budget was used up
+    pub skipped: Var<u32>,
+    /// execution time of the last poll in microseconds
+    pub exec_time: Var<u32>,
+    last_run: SyncCell<Option<u64>>,
+}
+
+impl TaskClass {
+    /// Creates a task class, a lower `priority` value means a higher priority.
+    pub const fn new(name: &'static str, priority: u8, period_us: u32) -> TaskClass {
+        TaskClass {
+            name,
+            priority,
+            period_us: SyncCell::new(period_us),
+            runs: Var::<u32>::new(),
+            skipped: Var::<u32>::new(),
+            exec_time: Var::<u32>::new(),
+            last_run: SyncCell::new(None),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// returns the period in microseconds, 0 polls the class in every cycle
+    pub fn period(&self) -> u32 {
+        self.period_us.get()
+    }
+
+    pub fn set_period(&self, period_us: u32) {
+        self.period_us.set(period_us);
+    }
+
+    /// returns true if `other` is this task class
+    pub fn is(&self, other: &TaskClass) -> bool {
+        core::ptr::eq(self, other)
+    }
+
+    /// returns true if the period elapsed since the class was polled last
+    pub fn is_due(&self, now: u64) -> bool {
+        match self.last_run.get() {
+            Some(last_run) => now.saturating_sub(last_run) >= self.period_us.get() as u64,
+            None => true,
+        }
+    }
+
+    fn mark_run(&self, now: u64) {
+        let period = self.period_us.get() as u64;
+        // keep the phase of the period unless the class fell behind by more than a period
+        let next = match self.last_run.get() {
+            Some(last_run) if period > 0 && now - last_run < 2 * period => last_run + period,
+            _ => now,
+        };
+        self.last_run.set(Some(next));
+        self.runs.set(self.runs.get().wrapping_add(1));
+    }
+}
+
+/// Polls task classes by priority.
+pub struct Scheduler<'a, const N: usize> {
+    classes: [&'a TaskClass; N],
+}
+
+impl<'a, const N: usize> Scheduler<'a, N> {
+    /// Creates a scheduler, the classes are sorted by priority.
+    pub const fn new(mut classes: [&'a TaskClass; N]) -> Self {
+        let mut i = 0;
+        while i < N {
+            let mut j = 0;
+            while j + 1 < N - i {
+                if classes[j].priority > classes[j + 1].priority {
+                    let class = classes[j];
+                    classes[j] = classes[j + 1];
+                    classes[j + 1] = class;
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        Scheduler { classes }
+    }
+
+    /// returns the task classes, highest priority first
+    pub fn classes(&self) -> &[&'a TaskClass; N] {
+        &self.classes
+    }
+
+    /// Polls all task classes that are due in this cycle, highest priority first.
+    ///
+    /// The classes with the highest priority are always polled when they are due. Lower
+    /// priority classes are only polled while less than `budget_us` microseconds elapsed since
+    /// the start of the cycle, otherwise they are retried in the next cycle.
+    /// `clock` returns a free running timestamp in microseconds, the period is checked
+    /// against `time::current_time`.
+    pub fn run_cycle(&self, budget_us: u32, clock: impl Fn() -> u64, mut poll: impl FnMut(&TaskClass)) {
+        let now = current_time();
+        let start = clock();
+        let top_priority = self.classes.first().map(|class| class.priority);
+        for class in self.classes.iter() {
+            if !class.is_due(now) {
+                continue;
+            }
+            let class_start = clock();
+            if Some(class.priority) != top_priority && class_start.saturating_sub(start) >= budget_us as u64 {
+                class.skipped.set(class.skipped.get().wrapping_add(1));
+                continue;
+            }
+            class.mark_run(now);
+            poll(class);
+            let elapsed = clock().saturating_sub(class_start);
+            class.exec_time.set(elapsed.min(u32::MAX as u64) as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn budget_skips_only_lower_priorities() {
+        let fast = TaskClass::new("fast", 0, 0);
+        let io = TaskClass::new("io", 0, 0);
+        let log = TaskClass::new("log", 1, 0);
+        let scheduler = Scheduler::new([&log, &io, &fast]);
+        // every clock reading advances by 500 us, the budget is used up after the first poll
+        let time = Cell::new(0);
+        let clock = || time.replace(time.get() + 500);
+        let mut polled = Vec::new();
+        scheduler.run_cycle(800, clock, |class| polled.push(class.name()));
+        assert_eq!(polled, ["io", "fast"]);
+        assert_eq!(io.skipped.get(), 0);
+        assert_eq!(log.skipped.get(), 1);
+    }
+}
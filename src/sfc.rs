@@ -0,0 +1,409 @@
+//! Sequential Function Charts in the style of IEC 61131-3.
+//!
+//! A sequence consists of statically allocated `Step`s that are combined in an `Sfc`.
+//! Step actions and transitions are written as plain code that runs once per cycle:
+//!
+//! ```ignore
+//! static FILL: Step = Step::new();
+//! static HEAT: Step = Step::new();
+//! static DRAIN: Step = Step::new();
+//! static BATCH: Sfc<'static, 3> = Sfc::new([&FILL, &HEAT, &DRAIN], 0);
+//!
+//! BATCH.run(|sfc| {
+//!     VALVE_IN.set(FILL.is_active());
+//!     HEATER.set(HEAT.is_active());
+//!     VALVE_OUT.set(DRAIN.is_active());
+//!
+//!     sfc.transition(&[&FILL], &[&HEAT], LEVEL.get() > 800);
+//!     sfc.transition(&[&HEAT], &[&DRAIN], TEMP.get() > 60 || HEAT.elapsed() > Duration::from_secs(600));
+//!     sfc.transition(&[&DRAIN], &[&FILL], LEVEL.get() < 10);
+//! })
+//! .await;
+//! ```
+//!
+//! Parallel branches are expressed by transitions with several target steps
+//! (divergence) or several source steps (convergence).
+
+use crate::sync::SyncCell;
+use crate::time::{current_time, wait_next_cycle};
+use crate::var::{Var, VarProps};
+use core::time::Duration;
+
+/// Execution state of a sequence, exposed as `Sfc::state`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum SfcState {
+    /// the sequence was not started yet
+    Idle = 0,
+    /// steps are active and transitions are evaluated
+    Running = 1,
+    /// steps stay active, but transitions are not evaluated and step time is frozen
+    Paused = 2,
+    /// all steps were deactivated, a reset restarts the sequence from the initial step
+    Aborted = 3,
+}
+
+/// Commands that can be written to `Sfc::command` by the host.
+/// The command is executed at the start of the next cycle and the variable is cleared.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum SfcCommand {
+    None = 0,
+    Pause = 1,
+    Resume = 2,
+    Abort = 3,
+    /// deactivates all steps and activates the step with the index in `Sfc::jump_to`
+    Jump = 4,
+    /// restarts the sequence from the initial step
+    Reset = 5,
+}
+
+/// A single step of a sequence.
+pub struct Step {
+    /// true while the step is active
+    pub active: Var<bool>,
+    /// time in milliseconds since the step was activated
+    pub time: Var<u32>,
+    activated_at: SyncCell<u64>,
+    /// activated during the current cycle, must not be left in the same cycle
+    fresh: SyncCell<bool>,
+    /// first cycle in which the step actions run
+    entry: SyncCell<bool>,
+}
+
+impl Step {
+    pub const fn new() -> Step {
+        Step {
+            active: Var::<bool>::new(),
+            time: Var::<u32>::new(),
+            activated_at: SyncCell::new(0),
+            fresh: SyncCell::new(false),
+            entry: SyncCell::new(false),
+        }
+    }
+
+    /// returns true if the step is active
+    pub fn is_active(&self) -> bool {
+        self.active.get()
+    }
+
+    /// returns true in the first cycle the step is active,
+    /// useful for actions that should only run once when entering the step
+    pub fn is_entry(&self) -> bool {
+        self.entry.get() && self.active.get()
+    }
+
+    /// returns the time since the step was activated
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.time.get() as u64)
+    }
+
+    fn activate(&self) {
+        self.activated_at.set(current_time());
+        self.time.set(0);
+        self.active.set(true);
+        self.fresh.set(true);
+    }
+
+    fn deactivate(&self) {
+        self.active.set(false);
+        self.fresh.set(false);
+        self.entry.set(false);
+    }
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sequence of steps.
+pub struct Sfc<'a, const N: usize> {
+    steps: [&'a Step; N],
+    initial: usize,
+    /// current `SfcState`
+    pub state: Var<u8>,
+    /// `SfcCommand` to execute in the next cycle
+    pub command: Var<u8>,
+    /// target step index for `SfcCommand::Jump`
+    pub jump_to: Var<u16>,
+    /// index + 1 of the first active step, 0 if no step is active
+    pub step: Var<u16>,
+    paused_at: SyncCell<u64>,
+}
+
+impl<'a, const N: usize> Sfc<'a, N> {
+    /// Creates a sequence from its steps, `initial` is the index of the initial step.
+    pub const fn new(steps: [&'a Step; N], initial: usize) -> Self {
+        Sfc {
+            steps,
+            initial,
+            state: Var::<u8>::new(),
+            command: Var::<u8>::new(),
+            jump_to: Var::<u16>::new(),
+            step: Var::<u16>::new(),
+            paused_at: SyncCell::new(0),
+        }
+    }
+
+    /// returns the current execution state
+    pub fn get_state(&self) -> SfcState {
+        match self.state.get() {
+            1 => SfcState::Running,
+            2 => SfcState::Paused,
+            3 => SfcState::Aborted,
+            _ => SfcState::Idle,
+        }
+    }
+
+    /// returns true if transitions are evaluated
+    pub fn is_running(&self) -> bool {
+        self.get_state() == SfcState::Running
+    }
+
+    /// pauses the sequence at the start of the next cycle
+    pub fn pause(&self) {
+        self.command.set(SfcCommand::Pause as u8);
+    }
+
+    /// resumes a paused sequence at the start of the next cycle
+    pub fn resume(&self) {
+        self.command.set(SfcCommand::Resume as u8);
+    }
+
+    /// aborts the sequence at the start of the next cycle
+    pub fn abort(&self) {
+        self.command.set(SfcCommand::Abort as u8);
+    }
+
+    /// restarts the sequence from the initial step at the start of the next cycle
+    pub fn reset(&self) {
+        self.command.set(SfcCommand::Reset as u8);
+    }
+
+    /// jumps to the given step at the start of the next cycle,
+    /// steps that are not part of this sequence are ignored
+    pub fn jump(&self, step: &Step) {
+        if let Some(index) = self.index_of(step) {
+            self.jump_to.set(index as u16);
+            self.command.set(SfcCommand::Jump as u8);
+        }
+    }
+
+    fn index_of(&self, step: &Step) -> Option<usize> {
+        self.steps.iter().position(|s| core::ptr::eq(*s, step))
+    }
+
+    fn set_state(&self, state: SfcState) {
+        self.state.set(state as u8);
+    }
+
+    fn restart_at(&self, index: usize) {
+        for step in self.steps.iter() {
+            step.deactivate();
+        }
+        if let Some(step) = self.steps.get(index) {
+            step.activate();
+        }
+        self.set_state(SfcState::Running);
+    }
+
+    fn execute_command(&self) {
+        let command = match self.command.get() {
+            1 => SfcCommand::Pause,
+            2 => SfcCommand::Resume,
+            3 => SfcCommand::Abort,
+            4 => SfcCommand::Jump,
+            5 => SfcCommand::Reset,
+            _ => SfcCommand::None,
+        };
+        if self.command.get() != SfcCommand::None as u8 {
+            self.command.set(SfcCommand::None as u8);
+        }
+
+        match command {
+            SfcCommand::Pause if self.is_running() => {
+                self.paused_at.set(current_time());
+                self.set_state(SfcState::Paused);
+            }
+            SfcCommand::Resume if self.get_state() == SfcState::Paused => {
+                // shift the activation times so the paused time does not count
+                let paused = current_time().saturating_sub(self.paused_at.get());
+                for step in self.steps.iter() {
+                    step.activated_at.set(step.activated_at.get() + paused);
+                }
+                self.set_state(SfcState::Running);
+            }
+            SfcCommand::Abort => {
+                for step in self.steps.iter() {
+                    step.deactivate();
+                }
+                self.set_state(SfcState::Aborted);
+            }
+            SfcCommand::Jump => {
+                let index = self.jump_to.get() as usize;
+                if index < N {
+                    self.restart_at(index);
+                }
+            }
+            SfcCommand::Reset => self.restart_at(self.initial),
+            _ => (),
+        }
+    }
+
+    /// Executes pending commands and updates step flags and step times.
+    /// Must be called once at the start of every cycle, before actions and transitions
+    /// are evaluated. `run` does this automatically.
+    pub fn cycle(&self) {
+        if self.get_state() == SfcState::Idle {
+            self.restart_at(self.initial);
+        }
+        self.execute_command();
+
+        let now = current_time();
+        let running = self.is_running();
+        let mut first_active = 0;
+        for (index, step) in self.steps.iter().enumerate() {
+            // a step activated in the previous cycle runs its entry actions now
+            step.entry.set(step.fresh.get());
+            step.fresh.set(false);
+
+            if step.is_active() {
+                if running {
+                    let ms = now.saturating_sub(step.activated_at.get()) / 1000;
+                    step.time.set(ms.min(u32::MAX as u64) as u32);
+                }
+                if first_active == 0 {
+                    first_active = index as u16 + 1;
+                }
+            }
+        }
+        self.step.set(first_active);
+    }
+
+    /// Evaluates a transition.
+    ///
+    /// The transition fires if the sequence is running, all `from` steps are active
+    /// and `condition` is true. It then deactivates the `from` steps and activates the
+    /// `to` steps. Steps activated in the current cycle cannot be left before the next
+    /// cycle, so every step runs its actions at least once.
+    ///
+    /// Returns true if the transition fired.
+    pub fn transition(&self, from: &[&Step], to: &[&Step], condition: bool) -> bool {
+        if !condition
+            || !self.is_running()
+            || from.iter().any(|step| !step.is_active() || step.fresh.get())
+        {
+            return false;
+        }
+
+        for step in from.iter() {
+            step.deactivate();
+        }
+        for step in to.iter() {
+            step.activate();
+        }
+        true
+    }
+
+    /// Runs the sequence forever, calling `body` once per cycle after `cycle`.
+    pub async fn run(&self, mut body: impl FnMut(&Self)) {
+        loop {
+            self.cycle();
+            body(self);
+            wait_next_cycle().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::set_system_time;
+
+    #[test]
+    fn transitions_fire_once_per_step_and_cycle() {
+        let _time = crate::time::lock_time();
+        let fill = Step::new();
+        let heat = Step::new();
+        let mix = Step::new();
+        let drain = Step::new();
+        let sfc = Sfc::new([&fill, &heat, &mix, &drain], 0);
+        // all conditions are true, so the sequence advances by one step per cycle
+        let cycle = |time: u64| {
+            set_system_time(time);
+            sfc.cycle();
+            let entries = [fill.is_entry(), heat.is_entry(), mix.is_entry(), drain.is_entry()];
+            sfc.transition(&[&fill], &[&heat, &mix], true);
+            sfc.transition(&[&heat, &mix], &[&drain], true);
+            sfc.transition(&[&drain], &[&fill], true);
+            entries
+        };
+
+        assert_eq!(cycle(0), [true, false, false, false]);
+        assert_eq!(sfc.step.get(), 1);
+        assert!(heat.is_active() && mix.is_active() && !fill.is_active());
+        assert_eq!(cycle(1_000), [false, true, true, false]);
+        assert_eq!(sfc.step.get(), 2);
+        assert!(drain.is_active() && !heat.is_active() && !mix.is_active());
+        assert_eq!(cycle(2_000), [false, false, false, true]);
+        assert!(fill.is_active());
+    }
+
+    #[test]
+    fn step_time_and_commands() {
+        let _time = crate::time::lock_time();
+        let fill = Step::new();
+        let heat = Step::new();
+        let sfc = Sfc::new([&fill, &heat], 0);
+        set_system_time(0);
+        sfc.cycle();
+        assert_eq!(sfc.get_state(), SfcState::Running);
+        set_system_time(5_000);
+        sfc.cycle();
+        assert_eq!(fill.elapsed(), Duration::from_millis(5));
+
+        sfc.pause();
+        sfc.cycle();
+        assert!(!sfc.transition(&[&fill], &[&heat], true));
+        set_system_time(9_000);
+        sfc.cycle();
+        assert_eq!(fill.time.get(), 5);
+        sfc.resume();
+        set_system_time(10_000);
+        sfc.cycle();
+        // the 5 ms pause does not count
+        assert_eq!(fill.time.get(), 5);
+
+        sfc.jump(&heat);
+        sfc.cycle();
+        assert!(heat.is_active() && !fill.is_active());
+        assert_eq!(sfc.command.get(), SfcCommand::None as u8);
+        sfc.abort();
+        sfc.cycle();
+        assert_eq!((sfc.get_state(), sfc.step.get()), (SfcState::Aborted, 0));
+        sfc.reset();
+        sfc.cycle();
+        assert_eq!((sfc.get_state(), sfc.step.get()), (SfcState::Running, 1));
+    }
+
+    #[test]
+    fn clock_stepped_back() {
+        let _time = crate::time::lock_time();
+        let fill = Step::new();
+        let sfc = Sfc::new([&fill], 0);
+        set_system_time(50_000);
+        sfc.cycle();
+        sfc.pause();
+        sfc.cycle();
+        // e.g. a simulation that restarts its clock
+        set_system_time(0);
+        sfc.resume();
+        sfc.cycle();
+        assert_eq!(fill.time.get(), 0);
+        set_system_time(60_000);
+        sfc.cycle();
+        assert_eq!(fill.time.get(), 10);
+    }
+}
@@ -1,4 +1,11 @@
-use core::{cell::Cell, ops::Deref};
+use crate::poll::poll_called;
+use core::{
+    cell::{Cell, UnsafeCell},
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    task::Poll,
+};
+use futures::future;
 
 /// A thin wrapper around `core::cell::Cell` that implements the `Sync` trait.
 ///
@@ -25,3 +32,497 @@ impl<T> Deref for SyncCell<T> {
 }
 
 unsafe impl<T: Sync> Sync for SyncCell<T> {}
+
+/// A value that is signaled by one task and taken by another one.
+///
+/// Signaling again before the value was taken overwrites the previous value.
+/// Like `SyncCell`, all async primitives in this module assume a single threaded
+/// executor and must not be accessed from interrupt handlers.
+pub struct Signal<T> {
+    value: SyncCell<Option<T>>,
+}
+
+impl<T> Signal<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: SyncCell::new(None),
+        }
+    }
+
+    /// signals a value, overwrites a value that was not taken yet
+    pub fn signal(&self, value: T) {
+        self.value.set(Some(value));
+    }
+
+    /// removes a signaled value
+    pub fn reset(&self) {
+        self.value.set(None);
+    }
+
+    /// returns true if a value is signaled and was not taken yet
+    pub fn signaled(&self) -> bool {
+        let value = self.value.take();
+        let signaled = value.is_some();
+        self.value.set(value);
+        signaled
+    }
+
+    /// takes the signaled value without waiting
+    pub fn try_take(&self) -> Option<T> {
+        self.value.take()
+    }
+
+    /// waits for a value to be signaled and takes it
+    pub async fn wait(&self) -> T {
+        future::poll_fn(|_| {
+            poll_called();
+            match self.value.take() {
+                Some(value) => Poll::Ready(value),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+impl<T> Default for Signal<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An async mutex, e.g. to share a serial port between tasks.
+///
+/// The lock is released when the `MutexGuard` is dropped. Waiting tasks are not
+/// queued, the first task polled after the release gets the lock.
+pub struct Mutex<T> {
+    locked: SyncCell<bool>,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: access to `value` is guarded by `locked`, see `SyncCell` for the threading assumptions
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: SyncCell::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// returns true if the mutex is locked
+    pub fn is_locked(&self) -> bool {
+        self.locked.get()
+    }
+
+    /// locks the mutex if it is not locked
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.locked.replace(true) {
+            None
+        } else {
+            Some(MutexGuard { mutex: self })
+        }
+    }
+
+    /// waits until the mutex can be locked
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        future::poll_fn(|_| {
+            poll_called();
+            match self.try_lock() {
+                Some(guard) => Poll::Ready(guard),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Grants access to the value of a locked `Mutex`.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.set(false);
+    }
+}
+
+/// A counting semaphore.
+pub struct Semaphore {
+    permits: SyncCell<usize>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: SyncCell::new(permits),
+        }
+    }
+
+    /// returns the number of available permits
+    pub fn available(&self) -> usize {
+        self.permits.get()
+    }
+
+    /// adds permits, e.g. to return permits that were forgotten
+    pub fn release(&self, permits: usize) {
+        self.permits.set(self.permits.get() + permits);
+    }
+
+    /// acquires a permit if one is available
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        match self.permits.get() {
+            0 => None,
+            permits => {
+                self.permits.set(permits - 1);
+                Some(SemaphorePermit { semaphore: self })
+            }
+        }
+    }
+
+    /// waits until a permit is available and acquires it
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        future::poll_fn(|_| {
+            poll_called();
+            match self.try_acquire() {
+                Some(permit) => Poll::Ready(permit),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// A permit of a `Semaphore`, it is returned when dropped.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    /// consumes the permit without returning it to the semaphore
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release(1);
+    }
+}
+
+/// A bounded multi-producer, multi-consumer channel with capacity `N`.
+pub struct Channel<T, const N: usize> {
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+    head: SyncCell<usize>,
+    len: SyncCell<usize>,
+}
+
+// SAFETY: only initialized slots are read, see `SyncCell` for the threading assumptions
+unsafe impl<T: Send, const N: usize> Sync for Channel<T, N> {}
+
+impl<T, const N: usize> Channel<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+            head: SyncCell::new(0),
+            len: SyncCell::new(0),
+        }
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        // SAFETY: index % N stays within the buffer
+        unsafe { (self.buffer.get() as *mut T).add(index % N) }
+    }
+
+    /// returns the number of queued messages
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// returns true if no message is queued
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    /// returns true if no more messages can be queued
+    pub fn is_full(&self) -> bool {
+        self.len.get() >= N
+    }
+
+    /// returns the maximum number of queued messages
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// queues a message, returns it back if the channel is full
+    pub fn try_send(&self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        let len = self.len.get();
+        // SAFETY: the slot behind the last message is not initialized
+        unsafe { self.slot(self.head.get() + len).write(value) };
+        self.len.set(len + 1);
+        Ok(())
+    }
+
+    /// waits until the channel has room and queues a message
+    pub async fn send(&self, value: T) {
+        let mut value = Some(value);
+        future::poll_fn(|_| {
+            poll_called();
+            match value.take().map(|value| self.try_send(value)) {
+                Some(Err(rejected)) => {
+                    value = Some(rejected);
+                    Poll::Pending
+                }
+                _ => Poll::Ready(()),
+            }
+        })
+        .await
+    }
+
+    /// takes the oldest message without waiting
+    pub fn try_recv(&self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let head = self.head.get();
+        // SAFETY: the slot at head is initialized and marked as free below
+        let value = unsafe { self.slot(head).read() };
+        self.head.set((head + 1) % N);
+        self.len.set(self.len.get() - 1);
+        Some(value)
+    }
+
+    /// waits for a message and takes it
+    pub async fn recv(&self) -> T {
+        future::poll_fn(|_| {
+            poll_called();
+            match self.try_recv() {
+                Some(value) => Poll::Ready(value),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+impl<T, const N: usize> Default for Channel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Channel<T, N> {
+    fn drop(&mut self) {
+        while self.try_recv().is_some() {}
+    }
+}
+
+/// Broadcasts the latest value to any number of receivers.
+pub struct Watch<T> {
+    value: SyncCell<T>,
+    version: SyncCell<u32>,
+}
+
+impl<T: Copy> Watch<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: SyncCell::new(value),
+            version: SyncCell::new(0),
+        }
+    }
+
+    /// publishes a new value, all receivers see it as changed
+    pub fn send(&self, value: T) {
+        self.value.set(value);
+        self.version.set(self.version.get().wrapping_add(1));
+    }
+
+    /// returns the latest value
+    pub fn get(&self) -> T {
+        self.value.get()
+    }
+
+    /// creates a receiver, the current value counts as seen
+    pub fn receiver(&self) -> WatchReceiver<'_, T> {
+        WatchReceiver {
+            watch: self,
+            seen: self.version.get(),
+        }
+    }
+}
+
+/// Receives the values of a `Watch`.
+pub struct WatchReceiver<'a, T> {
+    watch: &'a Watch<T>,
+    seen: u32,
+}
+
+impl<T: Copy> WatchReceiver<'_, T> {
+    /// returns the latest value and marks it as seen
+    pub fn get(&mut self) -> T {
+        self.seen = self.watch.version.get();
+        self.watch.get()
+    }
+
+    /// returns the latest value if it was not seen yet
+    pub fn try_changed(&mut self) -> Option<T> {
+        match self.watch.version.get() == self.seen {
+            true => None,
+            false => Some(self.get()),
+        }
+    }
+
+    /// waits until a value was sent that was not seen yet and returns it
+    pub async fn changed(&mut self) -> T {
+        future::poll_fn(|_| {
+            poll_called();
+            match self.try_changed() {
+                Some(value) => Poll::Ready(value),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Context;
+    use futures::task::noop_waker_ref;
+
+    fn poll<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn signal_keeps_latest_value() {
+        let signal = Signal::new();
+        let wait = signal.wait();
+        futures::pin_mut!(wait);
+        assert_eq!(poll(wait.as_mut()), Poll::Pending);
+        signal.signal(1);
+        signal.signal(2);
+        assert!(signal.signaled());
+        assert_eq!(poll(wait.as_mut()), Poll::Ready(2));
+        assert!(!signal.signaled());
+        assert_eq!(signal.try_take(), None);
+    }
+
+    #[test]
+    fn mutex_first_poll_after_release_wins() {
+        let mutex = Mutex::new(0);
+        let guard = mutex.try_lock().unwrap();
+        let first = mutex.lock();
+        let second = mutex.lock();
+        futures::pin_mut!(first, second);
+        assert!(poll(first.as_mut()).is_pending());
+        assert!(poll(second.as_mut()).is_pending());
+        drop(guard);
+        // no queue, the second waiter is polled first and gets the lock
+        let Poll::Ready(mut guard) = poll(second.as_mut()) else { panic!("not locked") };
+        *guard += 1;
+        assert!(poll(first.as_mut()).is_pending());
+        drop(guard);
+        let Poll::Ready(guard) = poll(first.as_mut()) else { panic!("not locked") };
+        assert_eq!(*guard, 1);
+        assert!(mutex.is_locked());
+        drop(guard);
+        assert!(!mutex.is_locked());
+    }
+
+    #[test]
+    fn semaphore_permits() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire().is_none());
+        let acquire = semaphore.acquire();
+        futures::pin_mut!(acquire);
+        assert!(poll(acquire.as_mut()).is_pending());
+        drop(permit);
+        let Poll::Ready(permit) = poll(acquire.as_mut()) else { panic!("no permit") };
+        assert_eq!(semaphore.available(), 0);
+        permit.forget();
+        assert_eq!(semaphore.available(), 0);
+        semaphore.release(2);
+        assert_eq!(semaphore.available(), 2);
+    }
+
+    #[test]
+    fn channel_full_and_empty() {
+        let channel = Channel::<u8, 2>::new();
+        let recv = channel.recv();
+        futures::pin_mut!(recv);
+        assert_eq!(poll(recv.as_mut()), Poll::Pending);
+        assert_eq!(channel.try_send(1), Ok(()));
+        assert_eq!(channel.try_send(2), Ok(()));
+        assert!(channel.is_full());
+        assert_eq!(channel.try_send(3), Err(3));
+
+        let send = channel.send(3);
+        futures::pin_mut!(send);
+        assert_eq!(poll(send.as_mut()), Poll::Pending);
+        assert_eq!(poll(recv.as_mut()), Poll::Ready(1));
+        assert_eq!(poll(send.as_mut()), Poll::Ready(()));
+
+        // the messages wrap around the end of the buffer in order
+        assert_eq!(channel.try_recv(), Some(2));
+        assert_eq!(channel.try_recv(), Some(3));
+        assert_eq!(channel.try_recv(), None);
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn channel_drops_queued_messages() {
+        let value = std::rc::Rc::new(());
+        let channel = Channel::<_, 2>::new();
+        channel.try_send(value.clone()).unwrap();
+        drop(channel);
+        assert_eq!(std::rc::Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn watch_receivers_see_latest_value() {
+        let watch = Watch::new(0);
+        let mut early = watch.receiver();
+        watch.send(1);
+        let mut late = watch.receiver();
+        assert_eq!(late.try_changed(), None);
+        {
+            let changed = late.changed();
+            futures::pin_mut!(changed);
+            assert_eq!(poll(changed.as_mut()), Poll::Pending);
+            watch.send(2);
+            watch.send(3);
+            // intermediate values are skipped
+            assert_eq!(poll(changed.as_mut()), Poll::Ready(3));
+        }
+        assert_eq!(early.try_changed(), Some(3));
+        assert_eq!(early.try_changed(), None);
+        assert_eq!(late.get(), 3);
+    }
+}
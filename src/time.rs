@@ -20,6 +20,13 @@ pub fn set_system_time(us: u64) {
     CURRENT_TIME.set(us)
 }
 
+/// Serializes the unit tests that set the system time, they run in parallel threads.
+#[cfg(test)]
+pub(crate) fn lock_time() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 /// Waits until the system time passes the given timestamp in microseconds.
 pub async fn wait_until(time: u64) {
     future::poll_fn(|_| {
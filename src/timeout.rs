@@ -0,0 +1,148 @@
+use crate::{poll::poll_called, time::{current_time, wait_us}};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Error returned when a future did not finish before its deadline.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Timeout;
+
+/// Future returned by `FutureExt::timeout` and `FutureExt::deadline`.
+pub struct Deadline<F> {
+    future: F,
+    deadline: u64,
+}
+
+impl<F> Deadline<F> {
+    /// returns the deadline as system timestamp in microseconds
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+impl<F: Future> Future for Deadline<F> {
+    type Output = Result<F::Output, Timeout>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        poll_called();
+        let deadline = self.deadline;
+        // SAFETY: `future` is never moved out of the pinned struct
+        let future = unsafe { self.map_unchecked_mut(|s| &mut s.future) };
+        match future.poll(cx) {
+            Poll::Ready(output) => Poll::Ready(Ok(output)),
+            Poll::Pending if current_time() >= deadline => Poll::Ready(Err(Timeout)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Deadline extensions for futures.
+///
+/// Deadlines are compared against `time::current_time`, so they follow whatever
+/// clock the runtime feeds into `time::set_system_time`, including a simulated
+/// clock in host tests.
+pub trait FutureExt: Future + Sized {
+    /// Fails with `Timeout` if the future does not finish within `duration`,
+    /// measured from now.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// match DOOR_CLOSED.pos().timeout(Duration::from_secs(5)).await {
+    ///     Ok(()) => println!("door closed"),
+    ///     Err(Timeout) => println!("door did not close"),
+    /// }
+    /// ```
+    fn timeout(self, duration: Duration) -> Deadline<Self> {
+        self.deadline(current_time() + duration.as_micros() as u64)
+    }
+
+    /// Fails with `Timeout` if the future does not finish before the system
+    /// time reaches `deadline` (in microseconds).
+    fn deadline(self, deadline: u64) -> Deadline<Self> {
+        Deadline {
+            future: self,
+            deadline,
+        }
+    }
+}
+
+impl<F: Future> FutureExt for F {}
+
+/// Runs a fallible async operation up to `attempts` times.
+///
+/// After each failed attempt it waits for `backoff`, the wait time doubles with
+/// every retry. Returns the first `Ok` value or the last error.
+///
+/// # Example
+///
+/// ```ignore
+/// let reply = with_retry(3, Duration::from_millis(100), || {
+///     request().timeout(Duration::from_millis(500))
+/// })
+/// .await;
+/// ```
+pub async fn with_retry<T, E, Fut, Op>(attempts: u32, backoff: Duration, mut op: Op) -> Result<T, E>
+where
+    Op: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff_us = backoff.as_micros() as u64;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(_) => {
+                wait_us(backoff_us).await;
+                backoff_us = backoff_us.saturating_mul(2);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{set_system_time, wait_until};
+    use futures::task::noop_waker_ref;
+
+    #[test]
+    fn timeout_and_retry() {
+        let _time = crate::time::lock_time();
+        let mut cx = Context::from_waker(noop_waker_ref());
+        set_system_time(0);
+        let late = wait_until(5_000).timeout(Duration::from_millis(3));
+        assert_eq!(Deadline::deadline(&late), 3_000);
+        futures::pin_mut!(late);
+        set_system_time(2_000);
+        assert!(late.as_mut().poll(&mut cx).is_pending());
+        set_system_time(3_000);
+        assert_eq!(late.as_mut().poll(&mut cx), Poll::Ready(Err(Timeout)));
+
+        // every attempt times out after 1 ms, the backoff doubles from 1 ms
+        let attempts = core::cell::Cell::new(Vec::new());
+        let retry = with_retry(3, Duration::from_millis(1), || {
+            let mut started = attempts.take();
+            started.push(current_time());
+            attempts.set(started);
+            wait_until(u64::MAX).timeout(Duration::from_millis(1))
+        });
+        futures::pin_mut!(retry);
+        let mut time = 3_000;
+        let result = loop {
+            set_system_time(time);
+            if let Poll::Ready(result) = retry.as_mut().poll(&mut cx) {
+                break result;
+            }
+            time += 500;
+        };
+        assert_eq!(result, Err(Timeout));
+        assert_eq!(attempts.take(), [3_000, 5_000, 8_000]);
+        assert_eq!(time, 9_000);
+    }
+}
@@ -0,0 +1,571 @@
+//! Recording and replay of variable traces for regression tests.
+//!
+//! A `TraceRecorder` captures the changes of a set of variables once per cycle into a
+//! compact binary trace. On the device the inputs are captured right after they were
+//! updated (`PilotBindings::set_from_pilot_bindings` or the host writes), a host test
+//! replays them with a `Replayer` and compares the outputs with a golden trace.
+//!
+//! ```ignore
+//! // device
+//! static INPUTS: [&dyn MemVar; 2] = [&START_BUTTON, &LEVEL];
+//! static TRACE: TraceBuffer<4096> = TraceBuffer::new();
+//! static RECORDER: TraceRecorder<'static, 2> = TraceRecorder::new(INPUTS, &TRACE);
+//! INPUTS_BINDINGS.set_from_pilot_bindings(&plc_mem);
+//! RECORDER.capture();
+//!
+//! // host test
+//! let actual = Mutex::new(Vec::new());
+//! let outputs = TraceRecorder::new(OUTPUTS, &actual);
+//! let mut replayer = Replayer::new(&trace, &INPUTS)?;
+//! for time in (0..=replayer.end_time()).step_by(1_000) {
+//!     set_system_time(time);
+//!     replayer.apply(time);
+//!     run_cycle();
+//!     outputs.capture();
+//! }
+//! assert_eq!(trace::diff(&golden, &actual.lock().unwrap()), None);
+//! ```
+//!
+//! Format: the header `PTRC`, a version byte and the number of variables (u16), then
+//! one block per cycle with changes: time delta in microseconds, number of changes and
+//! per change the variable index, the value length and the raw value (`MemVar` buffer
+//! bytes). Numbers are LEB128 encoded. Values of up to 8 bytes are supported.
+
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{MemVar, VarBuffer};
+use core::cell::UnsafeCell;
+
+const MAGIC: [u8; 4] = *b"PTRC";
+const VERSION: u8 = 1;
+const HEADER: usize = 7;
+
+/// Maximum size of a recorded value.
+pub const MAX_VALUE: usize = 8;
+
+/// Destination of the trace bytes.
+pub trait TraceSink: Sync {
+    fn write(&self, bytes: &[u8]);
+}
+
+/// A trace in RAM, writes that do not fit are dropped and set the overflow flag.
+pub struct TraceBuffer<const S: usize> {
+    buffer: UnsafeCell<[u8; S]>,
+    len: SyncCell<usize>,
+    overflow: SyncCell<bool>,
+}
+
+// SAFETY: see `SyncCell` for the threading assumptions, written bytes are never changed
+unsafe impl<const S: usize> Sync for TraceBuffer<S> {}
+
+impl<const S: usize> TraceBuffer<S> {
+    pub const fn new() -> Self {
+        TraceBuffer {
+            buffer: UnsafeCell::new([0; S]),
+            len: SyncCell::new(0),
+            overflow: SyncCell::new(false),
+        }
+    }
+
+    /// returns the recorded trace
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.buffer.get() as *const u8, self.len.get()) }
+    }
+
+    /// returns true if bytes were dropped because the buffer was full
+    pub fn is_overflow(&self) -> bool {
+        self.overflow.get()
+    }
+
+    pub fn clear(&self) {
+        self.len.set(0);
+        self.overflow.set(false);
+    }
+}
+
+impl<const S: usize> Default for TraceBuffer<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const S: usize> TraceSink for TraceBuffer<S> {
+    fn write(&self, bytes: &[u8]) {
+        let len = self.len.get();
+        if self.overflow.get() || len + bytes.len() > S {
+            self.overflow.set(true);
+            return;
+        }
+        unsafe {
+            let start = (self.buffer.get() as *mut u8).add(len);
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), start, bytes.len());
+        }
+        self.len.set(len + bytes.len());
+    }
+}
+
+#[cfg(feature = "std")]
+impl TraceSink for std::sync::Mutex<Vec<u8>> {
+    fn write(&self, bytes: &[u8]) {
+        self.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(bytes);
+    }
+}
+
+/// Writes `value` LEB128 encoded into `buffer`, returns the number of bytes.
+fn encode(mut value: u64, buffer: &mut [u8]) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer[len] = byte;
+            return len + 1;
+        }
+        buffer[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+fn decode(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// A recorded value.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Value {
+    len: u8,
+    bytes: [u8; MAX_VALUE],
+}
+
+impl Value {
+    const EMPTY: Value = Value {
+        len: 0,
+        bytes: [0; MAX_VALUE],
+    };
+
+    fn read(var: &dyn MemVar) -> Value {
+        let mut buffer = VarBuffer::new();
+        // subvalue 1 is the value without forcing
+        let len = unsafe { var.to_buffer(buffer.as_mut_ptr(), 1) } as usize;
+        let mut value = Value::EMPTY;
+        value.len = len.min(MAX_VALUE) as u8;
+        value.bytes[..value.len as usize].copy_from_slice(&buffer.0[..value.len as usize]);
+        value
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// Captures the changes of `N` variables.
+pub struct TraceRecorder<'a, const N: usize> {
+    vars: [&'a dyn MemVar; N],
+    sink: &'a dyn TraceSink,
+    last: [SyncCell<Option<Value>>; N],
+    last_time: SyncCell<Option<u64>>,
+}
+
+impl<'a, const N: usize> TraceRecorder<'a, N> {
+    pub const fn new(vars: [&'a dyn MemVar; N], sink: &'a dyn TraceSink) -> Self {
+        assert!(N <= u16::MAX as usize);
+        TraceRecorder {
+            vars,
+            sink,
+            last: [const { SyncCell::new(None) }; N],
+            last_time: SyncCell::new(None),
+        }
+    }
+
+    /// Writes the changes since the last call, the first call writes the header and
+    /// all values. Call it once per cycle.
+    pub fn capture(&self) {
+        let now = current_time();
+        let last_time = match self.last_time.get() {
+            Some(time) => time,
+            None => {
+                self.sink.write(&MAGIC);
+                self.sink.write(&[VERSION]);
+                self.sink.write(&(N as u16).to_le_bytes());
+                0
+            }
+        };
+        // read every variable once, so the count matches the written changes even if a
+        // value is changed by an interrupt in between
+        let mut values = [Value::EMPTY; N];
+        for (value, var) in values.iter_mut().zip(self.vars.iter()) {
+            *value = Value::read(*var);
+        }
+        let changed = |i: &usize| Some(values[*i]) != self.last[*i].get();
+        let count = (0..N).filter(changed).count();
+        if count == 0 && self.last_time.get().is_some() {
+            return;
+        }
+        let mut buffer = [0u8; 20];
+        let len = encode(now.saturating_sub(last_time), &mut buffer);
+        let len = len + encode(count as u64, &mut buffer[len..]);
+        self.sink.write(&buffer[..len]);
+        for (i, value) in values.iter().enumerate() {
+            if Some(*value) == self.last[i].get() {
+                continue;
+            }
+            self.last[i].set(Some(*value));
+            let len = encode(i as u64, &mut buffer);
+            buffer[len] = value.len;
+            self.sink.write(&buffer[..len + 1]);
+            self.sink.write(value.as_bytes());
+        }
+        self.last_time.set(Some(now));
+    }
+
+    /// the next `capture` starts a new trace with a header
+    pub fn restart(&self) {
+        self.last_time.set(None);
+        for last in self.last.iter() {
+            last.set(None);
+        }
+    }
+}
+
+/// A change in a trace.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Change {
+    /// time in microseconds
+    pub time: u64,
+    pub index: u16,
+    pub value: Value,
+}
+
+/// Reason a trace could not be read.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TraceError {
+    /// the header is missing or has another version
+    InvalidHeader,
+    /// the trace was recorded with another number of variables
+    VarCount(u16),
+}
+
+/// Iterates over the changes of a trace, stops at the end or at malformed data.
+pub struct TraceReader<'b> {
+    bytes: &'b [u8],
+    vars: u16,
+    time: u64,
+    remaining: u64,
+}
+
+impl<'b> TraceReader<'b> {
+    pub fn new(trace: &'b [u8]) -> Result<Self, TraceError> {
+        if trace.len() < HEADER || trace[..4] != MAGIC || trace[4] != VERSION {
+            return Err(TraceError::InvalidHeader);
+        }
+        Ok(TraceReader {
+            bytes: &trace[HEADER..],
+            vars: u16::from_le_bytes([trace[5], trace[6]]),
+            time: 0,
+            remaining: 0,
+        })
+    }
+
+    /// number of variables in the trace
+    pub fn vars(&self) -> u16 {
+        self.vars
+    }
+}
+
+impl Iterator for TraceReader<'_> {
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Change> {
+        while self.remaining == 0 {
+            self.time = self.time.checked_add(decode(&mut self.bytes)?)?;
+            self.remaining = decode(&mut self.bytes)?;
+        }
+        self.remaining -= 1;
+        // a corrupt trace ends at the first invalid change
+        let index = decode(&mut self.bytes)?;
+        if index >= self.vars as u64 {
+            return None;
+        }
+        let index = index as u16;
+        let (&len, rest) = self.bytes.split_first()?;
+        let bytes = rest.get(..len as usize)?;
+        self.bytes = &rest[len as usize..];
+        let mut value = Value::EMPTY;
+        value.len = bytes.len().min(MAX_VALUE) as u8;
+        value.bytes[..value.len as usize].copy_from_slice(&bytes[..value.len as usize]);
+        Some(Change {
+            time: self.time,
+            index,
+            value,
+        })
+    }
+}
+
+/// Applies a recorded trace to variables.
+pub struct Replayer<'a, 'b> {
+    vars: &'a [&'a dyn MemVar],
+    reader: TraceReader<'b>,
+    next: Option<Change>,
+    end_time: u64,
+}
+
+impl<'a, 'b> Replayer<'a, 'b> {
+    /// `vars` must be the variables the trace was recorded with, in the same order
+    pub fn new(trace: &'b [u8], vars: &'a [&'a dyn MemVar]) -> Result<Self, TraceError> {
+        let mut reader = TraceReader::new(trace)?;
+        if reader.vars() as usize != vars.len() {
+            return Err(TraceError::VarCount(reader.vars()));
+        }
+        let end_time = TraceReader::new(trace)?.last().map_or(0, |change| change.time);
+        Ok(Replayer {
+            vars,
+            next: reader.next(),
+            reader,
+            end_time,
+        })
+    }
+
+    /// returns the time of the next change
+    pub fn next_time(&self) -> Option<u64> {
+        self.next.map(|change| change.time)
+    }
+
+    /// returns the time of the last change in the trace
+    pub fn end_time(&self) -> u64 {
+        self.end_time
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next.is_none()
+    }
+
+    /// Sets all variables that changed up to `time`, like the inputs were written in
+    /// the cycle. Returns the number of applied changes.
+    pub fn apply(&mut self, time: u64) -> usize {
+        let mut count = 0;
+        while let Some(change) = self.next.filter(|change| change.time <= time) {
+            if let Some(var) = self.vars.get(change.index as usize) {
+                let mut buffer = VarBuffer::new();
+                buffer.0[..change.value.len as usize].copy_from_slice(change.value.as_bytes());
+                unsafe { var.from_buffer(buffer.as_ptr(), 0) };
+                count += 1;
+            }
+            self.next = self.reader.next();
+        }
+        count
+    }
+}
+
+/// The first difference between two traces.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mismatch {
+    /// the headers differ or one of the traces is invalid
+    Header,
+    /// the changes at the same position differ
+    Change { expected: Change, actual: Change },
+    /// the expected trace has more changes
+    Missing(Change),
+    /// the actual trace has more changes
+    Unexpected(Change),
+}
+
+/// Compares a trace with a golden trace, returns the first difference.
+pub fn diff(expected: &[u8], actual: &[u8]) -> Option<Mismatch> {
+    let (mut expected, mut actual) = match (TraceReader::new(expected), TraceReader::new(actual)) {
+        (Ok(expected), Ok(actual)) if expected.vars() == actual.vars() => (expected, actual),
+        _ => return Some(Mismatch::Header),
+    };
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => return None,
+            (Some(expected), Some(actual)) if expected != actual => {
+                return Some(Mismatch::Change { expected, actual })
+            }
+            (Some(_), Some(_)) => (),
+            (Some(expected), None) => return Some(Mismatch::Missing(expected)),
+            (None, Some(actual)) => return Some(Mismatch::Unexpected(actual)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::set_system_time;
+    use crate::var::{Var, VarProps};
+
+    #[test]
+    fn leb128() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buffer = [0u8; 10];
+            let len = encode(value, &mut buffer);
+            let mut bytes = &buffer[..len];
+            assert_eq!(decode(&mut bytes), Some(value));
+            assert!(bytes.is_empty());
+        }
+        assert_eq!(decode(&mut &[0x80, 0x80][..]), None);
+    }
+
+    /// records `level` and `start` for 5 cycles of 1 ms, changes every second cycle
+    fn record(level: &Var<u16>, start: &Var<bool>, trace: &TraceBuffer<256>) {
+        let recorder = TraceRecorder::new([level, start], trace);
+        for cycle in 0..5u16 {
+            set_system_time(10_000 + cycle as u64 * 1_000);
+            if cycle % 2 == 1 {
+                level.set(level.get() + 300);
+                start.set(!start.get());
+            }
+            recorder.capture();
+        }
+    }
+
+    #[test]
+    fn replay_and_diff() {
+        let _time = crate::time::lock_time();
+        let level = Var::<u16>::new();
+        let start = Var::<bool>::new();
+        let golden = TraceBuffer::<256>::new();
+        record(&level, &start, &golden);
+        assert!(!golden.is_overflow());
+        let changes: Vec<(u64, u16)> = TraceReader::new(golden.bytes())
+            .unwrap()
+            .map(|change| (change.time, change.index))
+            .collect();
+        assert_eq!(changes, [(10_000, 0), (10_000, 1), (11_000, 0), (11_000, 1), (13_000, 0), (13_000, 1)]);
+
+        let replayed_level = Var::<u16>::new();
+        let replayed_start = Var::<bool>::new();
+        let vars: [&dyn MemVar; 2] = [&replayed_level, &replayed_start];
+        let mut replayer = Replayer::new(golden.bytes(), &vars).unwrap();
+        assert_eq!(replayer.end_time(), 13_000);
+        assert_eq!(replayer.apply(10_500), 2);
+        assert_eq!(replayer.next_time(), Some(11_000));
+        assert_eq!(replayer.apply(12_999), 2);
+        assert_eq!((replayed_level.get(), replayed_start.get()), (300, true));
+        assert_eq!(replayer.apply(13_000), 2);
+        assert_eq!((replayed_level.get(), replayed_start.get()), (600, false));
+        assert!(replayer.is_finished());
+
+        let actual = TraceBuffer::<256>::new();
+        record(&Var::<u16>::new(), &Var::<bool>::new(), &actual);
+        assert_eq!(diff(golden.bytes(), actual.bytes()), None);
+        // the same changes with other values
+        let other = TraceBuffer::<256>::new();
+        let other_level = Var::<u16>::new();
+        other_level.set(1);
+        record(&other_level, &Var::<bool>::new(), &other);
+        match diff(golden.bytes(), other.bytes()) {
+            Some(Mismatch::Change { expected, actual }) => {
+                assert_eq!((expected.time, expected.index), (10_000, 0));
+                assert_eq!(actual.value.as_bytes(), [1, 0]);
+            }
+            mismatch => panic!("unexpected {:?}", mismatch),
+        }
+    }
+
+    #[test]
+    fn invalid_traces() {
+        let level = Var::<u16>::new();
+        let vars: [&dyn MemVar; 1] = [&level];
+        assert_eq!(TraceReader::new(b"PTRC").err(), Some(TraceError::InvalidHeader));
+        assert_eq!(TraceReader::new(b"PTRC\x02\x01\x00").err(), Some(TraceError::InvalidHeader));
+        let two_vars = b"PTRC\x01\x02\x00";
+        assert_eq!(Replayer::new(two_vars, &vars).err(), Some(TraceError::VarCount(2)));
+        assert_eq!(diff(two_vars, b"PTRC\x01\x01\x00"), Some(Mismatch::Header));
+        // a truncated change ends the trace
+        let truncated = b"PTRC\x01\x01\x00\x00\x01\x00\x02\x07";
+        assert_eq!(TraceReader::new(truncated).unwrap().count(), 0);
+        // so does a change of an unknown variable or a time that overflows
+        let unknown_var = b"PTRC\x01\x01\x00\x00\x01\x01\x01\x07";
+        assert_eq!(TraceReader::new(unknown_var).unwrap().count(), 0);
+        let mut overflow = b"PTRC\x01\x01\x00".to_vec();
+        overflow.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        overflow.extend_from_slice(&[1, 0, 1, 7, 1, 1, 0, 1, 8]);
+        let changes: Vec<_> = TraceReader::new(&overflow).unwrap().map(|change| change.time).collect();
+        assert_eq!(changes, [u64::MAX]);
+        match diff(b"PTRC\x01\x01\x00", b"PTRC\x01\x01\x00\x00\x01\x00\x01\x07") {
+            Some(Mismatch::Unexpected(change)) => assert_eq!(change.value.as_bytes(), [7]),
+            mismatch => panic!("unexpected {:?}", mismatch),
+        }
+    }
+
+    #[test]
+    fn buffer_overflow() {
+        let trace = TraceBuffer::<8>::new();
+        trace.write(b"PTRC\x01\x01\x00");
+        trace.write(b"\x00\x01");
+        assert!(trace.is_overflow());
+        assert_eq!(trace.bytes().len(), 7);
+        trace.clear();
+        assert!(!trace.is_overflow());
+        assert!(trace.bytes().is_empty());
+    }
+
+    /// returns the next value of a list with every read, like an input set by an ISR
+    struct Sequence {
+        values: [u8; 3],
+        reads: SyncCell<usize>,
+    }
+
+    impl MemVar for Sequence {
+        unsafe fn to_buffer(&self, buffer: *mut u8, _subvalue: u8) -> u8 {
+            let reads = self.reads.get();
+            self.reads.set(reads + 1);
+            *buffer = self.values[reads.min(self.values.len() - 1)];
+            1
+        }
+
+        unsafe fn from_buffer(&self, _buffer: *const u8, _subvalue: u8) -> u8 {
+            1
+        }
+
+        unsafe fn is_dirty(&self) -> bool {
+            false
+        }
+
+        unsafe fn clear_dirty(&self) {}
+
+        unsafe fn get_forced(&self) -> u8 {
+            0
+        }
+
+        unsafe fn set_forced(&self, _value: u8) {}
+
+        unsafe fn get_subscribed(&self) -> u8 {
+            0
+        }
+
+        unsafe fn set_subscribed(&self, _value: u8) {}
+    }
+
+    #[test]
+    fn capture_reads_every_variable_once() {
+        let _time = crate::time::lock_time();
+        let input = Sequence {
+            values: [0, 0, 1],
+            reads: SyncCell::new(0),
+        };
+        let level = Var::<u8>::new();
+        let trace = TraceBuffer::<256>::new();
+        let recorder = TraceRecorder::new([&input, &level], &trace);
+        recorder.capture();
+        level.set(5);
+        recorder.capture();
+        recorder.capture();
+        let changes: Vec<(u16, Vec<u8>)> = TraceReader::new(trace.bytes())
+            .unwrap()
+            .map(|change| (change.index, change.value.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(changes, [(0, vec![0]), (1, vec![0]), (1, vec![5]), (0, vec![1])]);
+        assert_eq!(input.reads.get(), 3);
+    }
+}
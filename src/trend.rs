@@ -0,0 +1,447 @@
+//! Trend recorder that samples variables into a RAM ring buffer.
+//!
+//! ```ignore
+//! static TREND: Trend<'static, 2, 600> = Trend::new([&PRESSURE, &VALVE_POS]);
+//!
+//! TREND.period.set(100_000); // one sample per 100 ms with min/max of every cycle
+//! TREND.set_trigger(TriggerMode::Rising, 0, 8.5); // pressure rises above 8.5
+//! TREND.post_trigger.set(100);
+//! TREND.arm();
+//! loop_async! {{
+//!     TREND.cycle();
+//! }}
+//! ```
+//!
+//! Every stored sample holds the minimum and maximum of each channel over the sample
+//! period, so short spikes are not lost. The host configures the recorder through the
+//! `Var`s and downloads the samples through `MemVar`.
+
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{MemVar, ScalarVar, Var, VarProps};
+
+/// Size of a sample in the `MemVar` buffer without the channel values.
+pub const SAMPLE_HEADER: usize = 12;
+
+/// Trigger condition on a channel.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TriggerMode {
+    /// no trigger, the recorder runs continuously
+    Off = 0,
+    /// the channel rises above the level
+    Rising = 1,
+    /// the channel falls below the level
+    Falling = 2,
+    /// the channel crosses the level in either direction
+    Either = 3,
+}
+
+impl TriggerMode {
+    pub fn from_u8(value: u8) -> TriggerMode {
+        match value {
+            1 => TriggerMode::Rising,
+            2 => TriggerMode::Falling,
+            3 => TriggerMode::Either,
+            _ => TriggerMode::Off,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum TrendState {
+    Stopped = 0,
+    /// recording and waiting for the trigger
+    Armed = 1,
+    /// recording the post-trigger samples
+    Triggered = 2,
+    /// the recording is complete
+    Done = 3,
+}
+
+/// A recorded sample with the minimum and maximum of `C` channels.
+#[derive(Debug, Copy, Clone)]
+pub struct TrendSample<const C: usize> {
+    pub seq: u32,
+    /// system time in microseconds at the end of the sample period
+    pub timestamp: u64,
+    pub min: [f32; C],
+    pub max: [f32; C],
+}
+
+impl<const C: usize> TrendSample<C> {
+    const EMPTY: TrendSample<C> = TrendSample {
+        seq: 0,
+        timestamp: 0,
+        min: [0.0; C],
+        max: [0.0; C],
+    };
+}
+
+/// Records `C` channels into a ring buffer of `S` samples.
+pub struct Trend<'a, const C: usize, const S: usize> {
+    channels: [&'a dyn ScalarVar; C],
+    samples: [SyncCell<TrendSample<C>>; S],
+    next_seq: SyncCell<u32>,
+    /// samples of the running sample period
+    pending: SyncCell<Option<TrendSample<C>>>,
+    period_start: SyncCell<u64>,
+    last_value: SyncCell<Option<f64>>,
+    remaining: SyncCell<u16>,
+    /// next sequence number read by the host
+    cursor: SyncCell<u32>,
+    // the recorder runs on this state, `state` only mirrors it for the host
+    recorder: SyncCell<TrendState>,
+    /// `arm` was set in the last cycle, a forced command arms only once
+    arm_seen: SyncCell<bool>,
+    /// sample period in microseconds, 0 stores a sample in every cycle
+    pub period: Var<u32>,
+    /// `TriggerMode` as number
+    pub trigger_mode: Var<u8>,
+    /// index of the channel the trigger is evaluated on
+    pub trigger_channel: Var<u8>,
+    pub trigger_level: Var<f32>,
+    /// number of samples recorded after the trigger, the rest of the buffer holds the
+    /// samples before it
+    pub post_trigger: Var<u16>,
+    /// `TrendState` as number, a read-only mirror for the host
+    pub state: Var<u8>,
+    /// sequence number of the sample that contains the trigger
+    pub trigger_seq: Var<u32>,
+    /// host command: true arms the recorder, reset to false when done
+    pub arm: Var<bool>,
+}
+
+impl<'a, const C: usize, const S: usize> Trend<'a, C, S> {
+    pub const fn new(channels: [&'a dyn ScalarVar; C]) -> Self {
+        // a sample must fit into a MemVar buffer
+        assert!(C > 0 && S > 0 && SAMPLE_HEADER + 8 * C <= u8::MAX as usize);
+        Trend {
+            channels,
+            samples: [const { SyncCell::new(TrendSample::EMPTY) }; S],
+            next_seq: SyncCell::new(0),
+            pending: SyncCell::new(None),
+            period_start: SyncCell::new(0),
+            last_value: SyncCell::new(None),
+            remaining: SyncCell::new(0),
+            cursor: SyncCell::new(0),
+            recorder: SyncCell::new(TrendState::Stopped),
+            arm_seen: SyncCell::new(false),
+            period: Var::<u32>::new(),
+            trigger_mode: Var::<u8>::new(),
+            trigger_channel: Var::<u8>::new(),
+            trigger_level: Var::<f32>::new(),
+            post_trigger: Var::<u16>::new(),
+            state: Var::<u8>::new(),
+            trigger_seq: Var::<u32>::new(),
+            arm: Var::<bool>::new(),
+        }
+    }
+
+    pub fn set_trigger(&self, mode: TriggerMode, channel: u8, level: f32) {
+        self.trigger_mode.set(mode as u8);
+        self.trigger_channel.set(channel);
+        self.trigger_level.set(level);
+    }
+
+    pub fn get_state(&self) -> TrendState {
+        self.recorder.get()
+    }
+
+    fn set_state(&self, state: TrendState) {
+        self.recorder.set(state);
+        self.state.set(state as u8);
+    }
+
+    /// clears the buffer and starts recording
+    pub fn arm(&self) {
+        self.next_seq.set(0);
+        self.cursor.set(0);
+        self.pending.set(None);
+        self.last_value.set(None);
+        self.period_start.set(current_time());
+        self.trigger_seq.set(0);
+        self.set_state(TrendState::Armed);
+    }
+
+    /// stops recording, the buffer is kept
+    pub fn stop(&self) {
+        self.set_state(TrendState::Stopped);
+    }
+
+    /// returns the sequence number of the oldest sample that is still stored
+    pub fn oldest_seq(&self) -> u32 {
+        self.next_seq.get().saturating_sub(S as u32)
+    }
+
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq.get()
+    }
+
+    /// returns the sample with sequence number `seq`, or the oldest stored sample if
+    /// `seq` was already overwritten
+    pub fn read(&self, seq: u32) -> Option<TrendSample<C>> {
+        let seq = seq.max(self.oldest_seq());
+        if seq >= self.next_seq.get() {
+            return None;
+        }
+        Some(self.samples[seq as usize % S].get())
+    }
+
+    fn triggered(&self) -> bool {
+        let channel = match self.channels.get(self.trigger_channel.get() as usize) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        let value = channel.get_f64();
+        let level = self.trigger_level.get() as f64;
+        let last = self.last_value.replace(Some(value));
+        let rising = matches!(last, Some(last) if last <= level && value > level);
+        let falling = matches!(last, Some(last) if last >= level && value < level);
+        match TriggerMode::from_u8(self.trigger_mode.get()) {
+            TriggerMode::Off => false,
+            TriggerMode::Rising => rising,
+            TriggerMode::Falling => falling,
+            TriggerMode::Either => rising || falling,
+        }
+    }
+
+    fn store(&self, mut sample: TrendSample<C>, now: u64) {
+        let seq = self.next_seq.get();
+        sample.seq = seq;
+        sample.timestamp = now;
+        self.samples[seq as usize % S].set(sample);
+        self.next_seq.set(seq + 1);
+        if self.get_state() == TrendState::Triggered {
+            let remaining = self.remaining.get().saturating_sub(1);
+            self.remaining.set(remaining);
+            if remaining == 0 {
+                self.set_state(TrendState::Done);
+            }
+        }
+    }
+
+    /// Samples the channels and checks the trigger, call it once per cycle.
+    pub fn cycle(&self) {
+        let arm = self.arm.get();
+        if arm && !self.arm_seen.get() {
+            self.arm.set(false);
+            self.arm();
+        }
+        self.arm_seen.set(arm);
+        let state = self.get_state();
+        if state == TrendState::Stopped || state == TrendState::Done {
+            return;
+        }
+        let now = current_time();
+        let mut sample = self.pending.get().unwrap_or(TrendSample {
+            min: [f32::MAX; C],
+            max: [f32::MIN; C],
+            ..TrendSample::EMPTY
+        });
+        for (i, channel) in self.channels.iter().enumerate() {
+            let value = channel.get_f64() as f32;
+            sample.min[i] = sample.min[i].min(value);
+            sample.max[i] = sample.max[i].max(value);
+        }
+        if state == TrendState::Armed && self.triggered() {
+            self.trigger_seq.set(self.next_seq.get());
+            let post = self.post_trigger.get().clamp(1, S as u16);
+            self.remaining.set(post);
+            self.set_state(TrendState::Triggered);
+        }
+        if now.saturating_sub(self.period_start.get()) >= self.period.get() as u64 {
+            self.period_start.set(now);
+            self.pending.set(None);
+            self.store(sample, now);
+        } else {
+            self.pending.set(Some(sample));
+        }
+    }
+}
+
+impl<const C: usize, const S: usize> MemVar for Trend<'_, C, S> {
+    /// subvalue 0 writes the next unread sample (seq u32, timestamp u64, then min and
+    /// max f32 per channel) and returns 0 if there is none, subvalue 1 writes the status
+    /// (state u8, channels u8, oldest seq u32, next seq u32, trigger seq u32)
+    unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8 {
+        let mut len = 0;
+        let mut write = |bytes: &[u8]| {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer.add(len), bytes.len());
+            len += bytes.len();
+        };
+        match subvalue {
+            1 => {
+                write(&[self.get_state() as u8, C as u8]);
+                write(&self.oldest_seq().to_le_bytes());
+                write(&self.next_seq.get().to_le_bytes());
+                write(&self.trigger_seq.get().to_le_bytes());
+            }
+            _ => {
+                let sample = match self.read(self.cursor.get()) {
+                    Some(sample) => sample,
+                    None => return 0,
+                };
+                self.cursor.set(sample.seq + 1);
+                write(&sample.seq.to_le_bytes());
+                write(&sample.timestamp.to_le_bytes());
+                for (min, max) in sample.min.iter().zip(sample.max.iter()) {
+                    write(&min.to_le_bytes());
+                    write(&max.to_le_bytes());
+                }
+            }
+        }
+        len as u8
+    }
+
+    /// sets the sequence number of the next sample to read (u32)
+    unsafe fn from_buffer(&self, buffer: *const u8, _subvalue: u8) -> u8 {
+        self.cursor.set((buffer as *const u32).read_unaligned());
+        4
+    }
+
+    /// true while there are unread samples
+    unsafe fn is_dirty(&self) -> bool {
+        self.cursor.get() < self.next_seq.get()
+    }
+
+    unsafe fn clear_dirty(&self) {}
+
+    unsafe fn get_forced(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_forced(&self, _value: u8) {}
+
+    unsafe fn get_subscribed(&self) -> u8 {
+        0
+    }
+
+    unsafe fn set_subscribed(&self, _value: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{lock_time, set_system_time};
+    use crate::var::VarBuffer;
+
+    fn run<const S: usize>(trend: &Trend<1, S>, input: &Var<f32>, values: &[f32]) {
+        for value in values {
+            input.set(*value);
+            set_system_time(current_time() + 1_000);
+            trend.cycle();
+        }
+    }
+
+    fn trigger_seq(mode: TriggerMode, values: &[f32]) -> Option<u32> {
+        let input = Var::<f32>::new();
+        let trend: Trend<1, 8> = Trend::new([&input]);
+        trend.set_trigger(mode, 0, 5.0);
+        trend.post_trigger.set(8);
+        trend.arm();
+        run(&trend, &input, values);
+        match trend.get_state() {
+            TrendState::Triggered => Some(trend.trigger_seq.get()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn trigger_modes() {
+        let _time = lock_time();
+        let up_down = [0.0, 5.0, 6.0, 4.0];
+        assert_eq!(trigger_seq(TriggerMode::Rising, &up_down), Some(2));
+        assert_eq!(trigger_seq(TriggerMode::Falling, &up_down), Some(3));
+        assert_eq!(trigger_seq(TriggerMode::Either, &[6.0, 4.0]), Some(1));
+        assert_eq!(trigger_seq(TriggerMode::Rising, &[6.0, 7.0]), None);
+        assert_eq!(trigger_seq(TriggerMode::Off, &up_down), None);
+    }
+
+    #[test]
+    fn post_trigger_and_ring() {
+        let _time = lock_time();
+        let input = Var::<f32>::new();
+        let trend: Trend<1, 4> = Trend::new([&input]);
+        trend.set_trigger(TriggerMode::Rising, 0, 5.0);
+        trend.post_trigger.set(2);
+        trend.arm.set(true);
+        run(&trend, &input, &[0.0, 1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(trend.get_state(), TrendState::Armed);
+        assert!(!trend.arm.get());
+        run(&trend, &input, &[6.0, 7.0, 8.0]);
+        assert_eq!(trend.get_state(), TrendState::Done);
+        assert_eq!(trend.state.get(), TrendState::Done as u8);
+        assert_eq!((trend.trigger_seq.get(), trend.next_seq()), (5, 7));
+
+        // the oldest samples were overwritten, reads start at the oldest stored one
+        assert_eq!(trend.oldest_seq(), 3);
+        let sample = trend.read(0).unwrap();
+        assert_eq!((sample.seq, sample.min[0]), (3, 3.0));
+        assert!(trend.read(7).is_none());
+    }
+
+    #[test]
+    fn min_max_over_period() {
+        let _time = lock_time();
+        set_system_time(0);
+        let input = Var::<f32>::new();
+        let trend: Trend<1, 4> = Trend::new([&input]);
+        trend.period.set(3_000);
+        trend.arm();
+        run(&trend, &input, &[2.0, -1.0, 4.0, 1.0]);
+        assert_eq!(trend.next_seq(), 1);
+        let sample = trend.read(0).unwrap();
+        assert_eq!((sample.min[0], sample.max[0], sample.timestamp), (-1.0, 4.0, 3_000));
+    }
+
+    #[test]
+    fn sample_layout() {
+        let _time = lock_time();
+        set_system_time(0);
+        let first = Var::<f32>::new();
+        let second = Var::<f32>::new();
+        let trend: Trend<2, 4> = Trend::new([&first, &second]);
+        trend.arm();
+        first.set(1.5);
+        second.set(-2.0);
+        set_system_time(1_000);
+        trend.cycle();
+
+        let mut buffer = VarBuffer::new();
+        unsafe {
+            assert!(trend.is_dirty());
+            assert_eq!(trend.to_buffer(buffer.as_mut_ptr(), 0) as usize, SAMPLE_HEADER + 16);
+            assert!(!trend.is_dirty());
+        }
+        let bytes = &buffer.0;
+        let f32_at = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        assert_eq!(&bytes[..4], &0u32.to_le_bytes());
+        assert_eq!(&bytes[4..12], &1_000u64.to_le_bytes());
+        assert_eq!([f32_at(12), f32_at(16), f32_at(20), f32_at(24)], [1.5, 1.5, -2.0, -2.0]);
+
+        unsafe { assert_eq!(trend.to_buffer(buffer.as_mut_ptr(), 1), 14) };
+        assert_eq!(&buffer.0[..2], &[TrendState::Armed as u8, 2]);
+        assert_eq!(&buffer.0[6..10], &1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn forced_variables_do_not_wedge() {
+        use crate::var::MemVar;
+        let _time = lock_time();
+        let input = Var::<f32>::new();
+        let trend: Trend<1, 4> = Trend::new([&input]);
+        trend.arm();
+        unsafe {
+            trend.state.from_buffer([TrendState::Done as u8].as_ptr(), 3);
+            trend.state.set_forced(1);
+            trend.arm.from_buffer([1u8].as_ptr(), 3);
+            trend.arm.set_forced(1);
+        }
+        run(&trend, &input, &[1.0, 2.0, 3.0]);
+        // the recorder keeps running and is armed only once
+        assert_eq!(trend.get_state(), TrendState::Armed);
+        assert_eq!(trend.next_seq(), 3);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::sync::SyncCell;
+use crate::var_float_impl;
 use crate::var_impl;
 use core::{
     future::Future,
@@ -65,10 +66,40 @@ impl TypeName for u8 {
 impl TypeName for i8 {
     const TYPE_NAME: &'static str = "i8";
 }
+impl TypeName for f64 {
+    const TYPE_NAME: &'static str = "f64";
+}
+impl TypeName for f32 {
+    const TYPE_NAME: &'static str = "f32";
+}
 impl TypeName for bool {
     const TYPE_NAME: &'static str = "bool";
 }
 
+/// A buffer for `MemVar` calls, aligned for all variable types.
+#[repr(C, align(8))]
+pub struct VarBuffer(pub [u8; 256]);
+
+impl VarBuffer {
+    pub const fn new() -> VarBuffer {
+        VarBuffer([0; 256])
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.0.as_mut_ptr()
+    }
+}
+
+impl Default for VarBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub trait MemVar: Sync {
     unsafe fn to_buffer(&self, buffer: *mut u8, subvalue: u8) -> u8;
     unsafe fn from_buffer(&self, buffer: *const u8, subvalue: u8) -> u8;
@@ -80,6 +111,13 @@ pub trait MemVar: Sync {
     unsafe fn set_subscribed(&self, value: u8);
 }
 
+/// Access to a numeric or boolean variable as `f64`, independent of its type.
+/// Booleans are 0.0 and 1.0, integer values saturate when set.
+pub trait ScalarVar: Sync {
+    fn get_f64(&self) -> f64;
+    fn set_f64(&self, value: f64);
+}
+
 pub trait VarProps<T> {
     /// gets the value of the variable
     fn get(&self) -> T;
@@ -217,6 +255,15 @@ pub struct Var<T: Default> {
     subscribed: SyncCell<SubscribeMode>,
 }
 
+impl<T: Default> Var<T> {
+    /// notifies the waveform recorder about a change of the value or forcing
+    #[inline(always)]
+    fn changed(&self) {
+        #[cfg(feature = "std")]
+        crate::vcd::var_changed(self as *const Self as *const ());
+    }
+}
+
 impl VarChange for Var<bool> {
     type VarType = bool;
 
@@ -249,6 +296,9 @@ var_impl!(i16);
 var_impl!(u8);
 var_impl!(i8);
 
+var_float_impl!(f64);
+var_float_impl!(f32);
+
 // ********** bool *********** //
 impl Var<bool> {
     pub const fn new() -> Var<bool> {
@@ -304,6 +354,7 @@ impl crate::var::MemVar for crate::var::Var<bool> {
             3 => self.forced_value.set(*(buffer as *mut u8) > 0),
             _ => self.set(*(buffer as *mut u8) > 0),
         };
+        self.changed();
         1
     }
 
@@ -328,6 +379,7 @@ impl crate::var::MemVar for crate::var::Var<bool> {
         } else {
             self.forced.set(false);
         }
+        self.changed();
     }
 
     unsafe fn get_subscribed(&self) -> u8 {
@@ -348,6 +400,19 @@ impl crate::var::MemVar for crate::var::Var<bool> {
     }
 }
 
+impl ScalarVar for Var<bool> {
+    fn get_f64(&self) -> f64 {
+        match self.get() {
+            true => 1.0,
+            false => 0.0,
+        }
+    }
+
+    fn set_f64(&self, value: f64) {
+        self.set(value != 0.0);
+    }
+}
+
 impl VarProps<bool> for Var<bool> {
     fn get(&self) -> bool {
         match self.forced.get() {
@@ -359,6 +424,7 @@ impl VarProps<bool> for Var<bool> {
     fn set(&self, value: bool) {
         if value != self.value.get() {
             self.value.set(value);
+            self.changed();
 
             match self.subscribed.get() {
                 SubscribeMode::Sticky => {
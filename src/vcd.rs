@@ -0,0 +1,300 @@
+//! Records variable changes on the host as a Value Change Dump (VCD) file, e.g. to
+//! view the timing of a simulated PLC program in GTKWave.
+//!
+//! ```ignore
+//! static VARS: [&dyn MemVar; 3] = [&START, &MOTOR, &SPEED];
+//! static INFO: [VariableInfo; 3] = [/* generated with the variables */];
+//!
+//! vcd::start_file("run.vcd", &INFO, &VARS)?;
+//! for cycle in 0..10_000 {
+//!     set_system_time(cycle * 1_000);
+//!     run_cycle();
+//! }
+//! vcd::stop()?;
+//! ```
+//!
+//! The variables are matched to the leaves of the `VariableInfo` tree in depth-first
+//! order, compound variables become scopes. Changes made with `VarProps::set`, the
+//! `NumVar` methods, `MemVar::from_buffer` and forcing are recorded with the time of
+//! `time::current_time()` in microseconds. A `computed::Computed` is recorded when it
+//! is recomputed, i.e. when the program or the host reads it after an input changed.
+
+use crate::time::current_time;
+use crate::var::{MemVar, VarBuffer, VariableInfo};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Bool,
+    /// integer with the width in bits
+    Integer(u8),
+    Real32,
+    Real64,
+}
+
+impl Kind {
+    fn from_type_name(ty: &str) -> Option<Kind> {
+        match ty {
+            "bool" => Some(Kind::Bool),
+            "u8" | "i8" => Some(Kind::Integer(8)),
+            "u16" | "i16" => Some(Kind::Integer(16)),
+            "u32" | "i32" => Some(Kind::Integer(32)),
+            "u64" | "i64" => Some(Kind::Integer(64)),
+            "f32" => Some(Kind::Real32),
+            "f64" => Some(Kind::Real64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Kind::Bool => 1,
+            Kind::Integer(bits) => bits as usize / 8,
+            Kind::Real32 => 4,
+            Kind::Real64 => 8,
+        }
+    }
+}
+
+struct Signal {
+    id: String,
+    kind: Kind,
+    var: &'static dyn MemVar,
+    last: Option<Vec<u8>>,
+}
+
+impl Signal {
+    fn read(&self) -> Vec<u8> {
+        let mut buffer = VarBuffer::new();
+        let len = unsafe { self.var.to_buffer(buffer.as_mut_ptr(), 0) } as usize;
+        buffer.0[..len.min(self.kind.size())].to_vec()
+    }
+
+    fn format(&self, bytes: &[u8]) -> String {
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        let raw = u64::from_le_bytes(raw);
+        match self.kind {
+            Kind::Bool => format!("{}{}", (raw != 0) as u8, self.id),
+            Kind::Integer(bits) => {
+                let value = match bits {
+                    64 => raw,
+                    bits => raw & ((1 << bits) - 1),
+                };
+                format!("b{:b} {}", value, self.id)
+            }
+            Kind::Real32 => format!("r{} {}", f32::from_bits(raw as u32), self.id),
+            Kind::Real64 => format!("r{} {}", f64::from_bits(raw), self.id),
+        }
+    }
+}
+
+struct Recorder {
+    writer: Box<dyn Write + Send>,
+    signals: Vec<Signal>,
+    by_address: HashMap<usize, usize>,
+    time: Option<u64>,
+}
+
+/// returns the VCD identifier for a signal number, built from the printable characters
+fn identifier(mut number: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (number % 94) as u8) as char);
+        number /= 94;
+        if number == 0 {
+            return id;
+        }
+        number -= 1;
+    }
+}
+
+fn address(var: &dyn MemVar) -> usize {
+    var as *const dyn MemVar as *const () as usize
+}
+
+impl Recorder {
+    fn declare(&mut self, infos: &[VariableInfo], vars: &mut impl Iterator<Item = &'static dyn MemVar>) -> io::Result<()> {
+        for info in infos {
+            if !info.fields.is_empty() {
+                writeln!(self.writer, "$scope module {} $end", info.name)?;
+                self.declare(info.fields, vars)?;
+                writeln!(self.writer, "$upscope $end")?;
+                continue;
+            }
+            let var = match vars.next() {
+                Some(var) => var,
+                None => return Ok(()),
+            };
+            let kind = match Kind::from_type_name(info.ty) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let id = identifier(self.signals.len());
+            match kind {
+                Kind::Bool => writeln!(self.writer, "$var wire 1 {} {} $end", id, info.name)?,
+                Kind::Integer(bits) => writeln!(self.writer, "$var wire {} {} {} $end", bits, id, info.name)?,
+                Kind::Real32 | Kind::Real64 => writeln!(self.writer, "$var real 64 {} {} $end", id, info.name)?,
+            }
+            self.by_address.insert(address(var), self.signals.len());
+            self.signals.push(Signal {
+                id,
+                kind,
+                var,
+                last: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self, index: usize) -> io::Result<()> {
+        let signal = &self.signals[index];
+        let value = signal.read();
+        if signal.last.as_ref() == Some(&value) {
+            return Ok(());
+        }
+        let line = signal.format(&value);
+        self.signals[index].last = Some(value);
+        let now = current_time();
+        if self.time != Some(now) {
+            writeln!(self.writer, "#{}", now)?;
+            self.time = Some(now);
+        }
+        writeln!(self.writer, "{}", line)
+    }
+}
+
+/// Starts recording to `writer`, a running recording is stopped.
+pub fn start(
+    writer: impl Write + Send + 'static,
+    info: &[VariableInfo],
+    vars: &'static [&'static dyn MemVar],
+) -> io::Result<()> {
+    stop()?;
+    let mut recorder = Recorder {
+        writer: Box::new(writer),
+        signals: Vec::new(),
+        by_address: HashMap::new(),
+        time: None,
+    };
+    writeln!(recorder.writer, "$version pilot_sys {} $end", env!("CARGO_PKG_VERSION"))?;
+    writeln!(recorder.writer, "$timescale 1us $end")?;
+    writeln!(recorder.writer, "$scope module plc $end")?;
+    recorder.declare(info, &mut vars.iter().copied())?;
+    writeln!(recorder.writer, "$upscope $end")?;
+    writeln!(recorder.writer, "$enddefinitions $end")?;
+    writeln!(recorder.writer, "#{}", current_time())?;
+    recorder.time = Some(current_time());
+    writeln!(recorder.writer, "$dumpvars")?;
+    for index in 0..recorder.signals.len() {
+        recorder.dump(index)?;
+    }
+    writeln!(recorder.writer, "$end")?;
+    *RECORDER.lock().unwrap_or_else(|e| e.into_inner()) = Some(recorder);
+    ACTIVE.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Starts recording to a file.
+pub fn start_file(
+    path: impl AsRef<Path>,
+    info: &[VariableInfo],
+    vars: &'static [&'static dyn MemVar],
+) -> io::Result<()> {
+    start(BufWriter::new(File::create(path)?), info, vars)
+}
+
+/// Stops recording and flushes the output.
+pub fn stop() -> io::Result<()> {
+    ACTIVE.store(false, Ordering::Release);
+    match RECORDER.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        Some(mut recorder) => {
+            // mark the end of the recording so the last values are shown with a duration
+            if recorder.time != Some(current_time()) {
+                writeln!(recorder.writer, "#{}", current_time())?;
+            }
+            recorder.writer.flush()
+        }
+        None => Ok(()),
+    }
+}
+
+/// Records the value of the variable at `var` if it is registered, called by `Var`.
+pub(crate) fn var_changed(var: *const ()) {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+    let mut recorder = RECORDER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(recorder) = recorder.as_mut() {
+        if let Some(index) = recorder.by_address.get(&(var as usize)).copied() {
+            // a failing writer must not disturb the program under test
+            let _ = recorder.dump(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computed::Computed;
+    use crate::time::set_system_time;
+    use crate::var::{Var, VarProps};
+    use std::sync::Arc;
+
+    static LEVEL: Var<u16> = Var::<u16>::new();
+    static HIGH: Computed<'static, bool, 1> = Computed::new([&LEVEL], || LEVEL.get() > 100, Var::<bool>::new());
+    static VARS: [&dyn MemVar; 2] = [&LEVEL, &HIGH];
+    static INFO: [VariableInfo; 2] = [
+        VariableInfo {
+            name: "level",
+            ty: "u16",
+            fields: &[],
+            field_number_offset: 0,
+        },
+        VariableInfo {
+            name: "high",
+            ty: "bool",
+            fields: &[],
+            field_number_offset: 0,
+        },
+    ];
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_vars_and_computed() {
+        let _time = crate::time::lock_time();
+        set_system_time(1_000);
+        let output = Output::default();
+        start(output.clone(), &INFO, &VARS).unwrap();
+        set_system_time(2_000);
+        LEVEL.set(150);
+        assert!(HIGH.get());
+        set_system_time(3_000);
+        stop().unwrap();
+        let vcd = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let changes = vcd.split("$enddefinitions $end\n").nth(1).unwrap();
+        assert!(vcd.contains("$var wire 16 ! level $end\n$var wire 1 \" high $end"));
+        assert_eq!(changes, "#1000\n$dumpvars\nb0 !\n0\"\n$end\n#2000\nb10010110 !\n1\"\n#3000\n");
+    }
+}
@@ -0,0 +1,270 @@
+//! Software watchdogs for async tasks.
+//!
+//! ```ignore
+//! static SEQ_TOKEN: CancelToken = CancelToken::new();
+//! static SEQ_WD: Watchdog = Watchdog::with_token("sequence", Duration::from_secs(30), &SEQ_TOKEN, ExpiryAction::Restart);
+//!
+//! // task
+//! SEQ_WD.register();
+//! SEQ_WD.supervise(|| async {
+//!     loop {
+//!         SEQ_WD.feed();
+//!         SENSOR.pos().await;
+//!         // ...
+//!     }
+//! })
+//! .await;
+//!
+//! // runtime, once per cycle
+//! watchdog::set_hardware_kick(kick_iwdg);
+//! watchdog::check_all();
+//! ```
+
+use crate::cancel::CancelToken;
+use crate::sync::SyncCell;
+use crate::time::current_time;
+use crate::var::{Var, VarProps};
+use core::future::Future;
+use core::panic::Location;
+use core::time::Duration;
+
+/// What happens when a watchdog expires.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExpiryAction {
+    /// only raise the alarm
+    Alarm,
+    /// raise the alarm and cancel the task through its `CancelToken`
+    Cancel,
+    /// raise the alarm, cancel the task and start it again in `Watchdog::supervise`
+    Restart,
+}
+
+/// true while at least one watchdog is expired
+pub static ALARM: Var<bool> = Var::<bool>::new();
+
+static WATCHDOGS: SyncCell<Option<&'static Watchdog>> = SyncCell::new(None);
+static HARDWARE_KICK: SyncCell<Option<fn()>> = SyncCell::new(None);
+
+/// A watchdog that a task must feed within its timeout.
+pub struct Watchdog {
+    name: &'static str,
+    timeout_us: u64,
+    token: Option<&'static CancelToken<'static>>,
+    action: ExpiryAction,
+    /// true while the watchdog is expired
+    pub expired: Var<bool>,
+    /// number of expiries
+    pub expiries: Var<u32>,
+    last_feed: SyncCell<u64>,
+    checkpoint: SyncCell<Option<&'static Location<'static>>>,
+    enabled: SyncCell<bool>,
+    registered: SyncCell<bool>,
+    next: SyncCell<Option<&'static Watchdog>>,
+}
+
+impl Watchdog {
+    /// creates a watchdog that only raises an alarm
+    pub const fn new(name: &'static str, timeout: Duration) -> Watchdog {
+        Watchdog {
+            name,
+            timeout_us: timeout.as_micros() as u64,
+            token: None,
+            action: ExpiryAction::Alarm,
+            expired: Var::<bool>::new(),
+            expiries: Var::<u32>::new(),
+            last_feed: SyncCell::new(0),
+            checkpoint: SyncCell::new(None),
+            enabled: SyncCell::new(true),
+            registered: SyncCell::new(false),
+            next: SyncCell::new(None),
+        }
+    }
+
+    /// creates a watchdog that cancels or restarts the task of `token` when it expires
+    pub const fn with_token(
+        name: &'static str,
+        timeout: Duration,
+        token: &'static CancelToken<'static>,
+        action: ExpiryAction,
+    ) -> Watchdog {
+        let mut watchdog = Self::new(name, timeout);
+        watchdog.token = Some(token);
+        watchdog.action = action;
+        watchdog
+    }
+
+    /// returns the name of the watched task
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Adds the watchdog to the watchdogs checked by `check_all` and feeds it.
+    #[track_caller]
+    pub fn register(&'static self) {
+        self.feed();
+        if !self.registered.replace(true) {
+            self.next.set(WATCHDOGS.replace(Some(self)));
+        }
+    }
+
+    /// Feeds the watchdog and records the caller as last checkpoint.
+    #[track_caller]
+    pub fn feed(&self) {
+        self.last_feed.set(current_time());
+        self.checkpoint.set(Some(Location::caller()));
+        self.expired.set(false);
+    }
+
+    /// Records the caller as last checkpoint without feeding the watchdog,
+    /// e.g. right before a long await.
+    #[track_caller]
+    pub fn checkpoint(&self) {
+        self.checkpoint.set(Some(Location::caller()));
+    }
+
+    /// returns the last recorded checkpoint
+    pub fn last_checkpoint(&self) -> Option<&'static Location<'static>> {
+        self.checkpoint.get()
+    }
+
+    /// stops watching, e.g. while the task legitimately waits for an operator
+    pub fn disable(&self) {
+        self.enabled.set(false);
+        self.expired.set(false);
+    }
+
+    /// starts watching again and feeds the watchdog
+    #[track_caller]
+    pub fn enable(&self) {
+        self.feed();
+        self.enabled.set(true);
+    }
+
+    /// checks the timeout, returns true if the watchdog is healthy
+    fn check(&self, now: u64) -> bool {
+        if !self.enabled.get() {
+            return true;
+        }
+        if self.expired.get() {
+            return false;
+        }
+        if now.saturating_sub(self.last_feed.get()) <= self.timeout_us {
+            return true;
+        }
+
+        self.expired.set(true);
+        self.expiries.set(self.expiries.get().wrapping_add(1));
+        match self.checkpoint.get() {
+            Some(location) => crate::println!(
+                "watchdog '{}' expired, last checkpoint {}:{}",
+                self.name,
+                location.file(),
+                location.line()
+            ),
+            None => crate::println!("watchdog '{}' expired", self.name),
+        }
+        if self.action != ExpiryAction::Alarm {
+            if let Some(token) = self.token {
+                token.cancel();
+            }
+        }
+        false
+    }
+
+    /// Runs the task created by `task` with the cancel token of the watchdog.
+    ///
+    /// With `ExpiryAction::Restart` a new task is created after an expiry of this watchdog
+    /// cancelled the previous one, otherwise this returns when the task finished or was
+    /// cancelled. Cancellations by others are never reset, the token stays cancelled.
+    pub async fn supervise<F, Fut>(&self, mut task: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let token = match self.token {
+            Some(token) => token,
+            None => return task().await,
+        };
+        loop {
+            if token.is_cancelled() {
+                return;
+            }
+            self.feed();
+            let restart = token.run(task()).await.is_err()
+                && self.action == ExpiryAction::Restart
+                && self.expired.get();
+            if !restart {
+                return;
+            }
+            // only the watchdog cancelled the token, a cancelled parent stays cancelled
+            token.reset();
+        }
+    }
+}
+
+/// Sets the hook that kicks the hardware watchdog,
+/// it is only called by `check_all` while all software watchdogs are healthy.
+pub fn set_hardware_kick(kick: fn()) {
+    HARDWARE_KICK.set(Some(kick));
+}
+
+/// Checks all registered watchdogs, must be called once per cycle by the runtime.
+/// Returns true if all watchdogs are healthy.
+pub fn check_all() -> bool {
+    let now = current_time();
+    let mut healthy = true;
+    let mut next = WATCHDOGS.get();
+    while let Some(watchdog) = next {
+        healthy &= watchdog.check(now);
+        next = watchdog.next.get();
+    }
+
+    ALARM.set(!healthy);
+    if healthy {
+        if let Some(kick) = HARDWARE_KICK.get() {
+            kick();
+        }
+    }
+    healthy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{lock_time, set_system_time};
+    use core::task::Context;
+    use futures::task::noop_waker_ref;
+
+    static TOKEN: CancelToken = CancelToken::new();
+    static WATCHDOG: Watchdog =
+        Watchdog::with_token("test", Duration::from_millis(10), &TOKEN, ExpiryAction::Restart);
+
+    #[test]
+    fn restarts_only_after_expiry() {
+        let _time = lock_time();
+        set_system_time(0);
+        let starts = SyncCell::new(0);
+        let supervise = WATCHDOG.supervise(|| {
+            starts.set(starts.get() + 1);
+            futures::future::pending()
+        });
+        futures::pin_mut!(supervise);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(supervise.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(starts.get(), 1);
+
+        // the watchdog expires and cancels the task, it is started again
+        set_system_time(20_000);
+        assert!(!WATCHDOG.check(20_000));
+        assert!(supervise.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(starts.get(), 2);
+        assert!(!TOKEN.is_cancelled() && !WATCHDOG.expired.get());
+
+        // an external cancel stops the task for good
+        TOKEN.cancel();
+        assert!(supervise.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(starts.get(), 2);
+        assert!(TOKEN.is_cancelled());
+        TOKEN.reset();
+    }
+}